@@ -0,0 +1,90 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::hint::black_box;
+
+use tokenizer::{bytes::ByteTokenizer, Tokenizer};
+
+/// Sample tokenizer shipped with the repo, trained on characters
+fn load_sample() -> Tokenizer<char> {
+    serde_json::from_str(include_str!("../tokenizer.json")).unwrap()
+}
+
+/// Build an input the tokenizer can encode by decoding a spread of token ids
+fn sample_input(tokenizer: &Tokenizer<char>, nb_tokens: usize) -> Vec<char> {
+    let mut ids: Vec<usize> = tokenizer.lookup.keys().copied().collect();
+    ids.sort();
+
+    let token_ids: Vec<usize> = (0..nb_tokens)
+        .map(|i| ids[(i * 7919) % ids.len()])
+        .collect();
+
+    let mut input = vec![];
+    tokenizer.detokenize(&token_ids, &mut input);
+    input
+}
+
+fn encode(c: &mut Criterion) {
+    let tokenizer = load_sample();
+    let compiled = tokenizer.compile();
+    let input = sample_input(&tokenizer, 100_000);
+
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(input.len() as u64));
+
+    group.bench_function("hashmap_trie", |b| {
+        b.iter(|| {
+            let mut token_buffer = Vec::with_capacity(input.len());
+            tokenizer.tokenize(black_box(&input), &mut token_buffer, &mut 0);
+            token_buffer
+        })
+    });
+
+    group.bench_function("compiled_trie", |b| {
+        b.iter(|| {
+            let mut token_buffer = Vec::with_capacity(input.len());
+            compiled.tokenize(black_box(&input), &mut token_buffer, &mut 0);
+            token_buffer
+        })
+    });
+
+    group.finish();
+}
+
+fn encode_bytes(c: &mut Criterion) {
+    // the sample vocabulary is ascii, reuse it as a byte vocabulary
+    let char_tokenizer = load_sample();
+    let mut tokenizer: Tokenizer<u8> = Tokenizer::default();
+    for (token_value, token) in &char_tokenizer.lookup {
+        let bytes: Vec<u8> = token.iter().map(|c| *c as u8).collect();
+        tokenizer.register(&bytes, *token_value);
+    }
+    let byte_tokenizer = ByteTokenizer::from(&tokenizer);
+
+    let input: Vec<u8> = sample_input(&char_tokenizer, 100_000)
+        .iter()
+        .map(|c| *c as u8)
+        .collect();
+
+    let mut group = c.benchmark_group("encode_bytes");
+    group.throughput(Throughput::Bytes(input.len() as u64));
+
+    group.bench_function("hashmap_trie", |b| {
+        b.iter(|| {
+            let mut token_buffer = Vec::with_capacity(input.len());
+            tokenizer.tokenize(black_box(&input), &mut token_buffer, &mut 0);
+            token_buffer
+        })
+    });
+
+    group.bench_function("byte_tokenizer", |b| {
+        b.iter(|| {
+            let mut token_buffer = Vec::with_capacity(input.len());
+            byte_tokenizer.tokenize(black_box(&input), &mut token_buffer, &mut 0);
+            token_buffer
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, encode, encode_bytes);
+criterion_main!(benches);
@@ -0,0 +1,490 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fmt::Debug,
+    fs,
+    hash::Hash,
+    io::{self, BufWriter, Write},
+    process,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokenizer::{
+    binary::BinaryElement,
+    bytes::ByteTokenizer,
+    dump::{self, RenderElement},
+    files::{is_byte_tokenizer, load, save, Format},
+    with_rayon::{parallel_generate_bytes, parallel_generate_with_max_length},
+    Tokenizer,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const USAGE: &str = "usage: bpe <command> [options]
+
+commands:
+  train    --vocab-size N [--max-length N] [--special NAME]... --output FILE [--format F] CORPUS...
+  encode   --tokenizer FILE [--binary] [--output FILE] INPUT
+  decode   --tokenizer FILE [--binary] [--output FILE] INPUT
+  inspect  --tokenizer FILE [--dump tsv|json]
+  convert  [--format F] INPUT OUTPUT
+
+formats (F): json, vocabulary, binary. Without --format it is taken from the
+output extension: .bin for binary, .vocab.json for vocabulary, json otherwise.
+Loaded tokenizers are validated unless --unchecked is given.
+--binary reads and writes ids as u32 little endian instead of text.
+--bytes works on raw file bytes instead of utf-8 text, with all 256 byte
+values in the base vocabulary. Tokenizer files of bytes, binary or json, are
+detected without it.";
+
+/// Options that never take a value
+const FLAGS: &[&str] = &["--binary", "--bytes", "--unchecked", "--help"];
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) if command != "--help" && command != "-h" => (command, rest),
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    let args = Args::parse(rest)?;
+    if args.flag("--help") {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    // the tokenizer that gets loaded, if any
+    let tokenizer_path = match command.as_str() {
+        "convert" => args.positional.first().map(|path| path.as_str()),
+        _ => args.value("--tokenizer"),
+    };
+    if args.flag("--bytes") || tokenizer_path.is_some_and(is_byte_tokenizer) {
+        dispatch::<u8>(command, &args)
+    } else {
+        dispatch::<char>(command, &args)
+    }
+}
+
+fn dispatch<T: Element>(command: &str, args: &Args) -> Result<()> {
+    match command {
+        "train" => train::<T>(args),
+        "encode" => encode::<T>(args),
+        "decode" => decode::<T>(args),
+        "inspect" => inspect::<T>(args),
+        "convert" => convert::<T>(args),
+        _ => Err(format!("unknown command {}\n\n{}", command, USAGE).into()),
+    }
+}
+
+/// Element types the binary works on, utf-8 text as char and raw files as u8
+trait Element:
+    Eq
+    + Hash
+    + Clone
+    + Debug
+    + Send
+    + Sync
+    + BinaryElement
+    + RenderElement
+    + Serialize
+    + DeserializeOwned
+{
+    fn read_input(path: &str) -> Result<Vec<Self>>;
+
+    fn write_output<W: Write>(elements: &[Self], writer: &mut W) -> Result<()>;
+
+    fn train(inputs: Vec<Vec<Self>>, vocab_size: usize, max_length: usize) -> Tokenizer<Self>;
+
+    fn encode(tokenizer: &Tokenizer<Self>, input: &[Self]) -> Vec<usize> {
+        let mut token_buffer: Vec<usize> = vec![];
+        tokenizer.tokenize(input, &mut token_buffer, &mut 0);
+        token_buffer
+    }
+}
+
+impl Element for char {
+    fn read_input(path: &str) -> Result<Vec<Self>> {
+        Ok(fs::read_to_string(path)?.chars().collect())
+    }
+
+    fn write_output<W: Write>(elements: &[Self], writer: &mut W) -> Result<()> {
+        writer.write_all(elements.iter().collect::<String>().as_bytes())?;
+        Ok(())
+    }
+
+    fn train(inputs: Vec<Vec<Self>>, vocab_size: usize, max_length: usize) -> Tokenizer<Self> {
+        // base vocabulary is whatever the corpus holds
+        let mut seen: HashSet<char> = HashSet::new();
+        let base_vocabulary: Vec<char> = inputs
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|elem| seen.insert(*elem))
+            .collect();
+
+        parallel_generate_with_max_length(inputs, base_vocabulary, vocab_size, max_length)
+    }
+}
+
+impl Element for u8 {
+    fn read_input(path: &str) -> Result<Vec<Self>> {
+        Ok(fs::read(path)?)
+    }
+
+    fn write_output<W: Write>(elements: &[Self], writer: &mut W) -> Result<()> {
+        writer.write_all(elements)?;
+        Ok(())
+    }
+
+    fn train(inputs: Vec<Vec<Self>>, vocab_size: usize, max_length: usize) -> Tokenizer<Self> {
+        parallel_generate_bytes(inputs, vocab_size, max_length)
+    }
+
+    fn encode(tokenizer: &Tokenizer<Self>, input: &[Self]) -> Vec<usize> {
+        let mut token_buffer: Vec<usize> = vec![];
+        ByteTokenizer::new(tokenizer).tokenize(input, &mut token_buffer, &mut 0);
+        token_buffer
+    }
+}
+
+/// Command line split into positional arguments, flags and valued options
+#[derive(Debug, Default)]
+struct Args {
+    positional: Vec<String>,
+    flags: HashSet<String>,
+    options: HashMap<String, Vec<String>>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if FLAGS.contains(&arg.as_str()) {
+                parsed.flags.insert(arg.to_owned());
+            } else if arg.starts_with("--") {
+                let value = args
+                    .next()
+                    .ok_or_else(|| format!("missing value for {}", arg))?;
+                parsed
+                    .options
+                    .entry(arg.to_owned())
+                    .or_default()
+                    .push(value.to_owned());
+            } else {
+                parsed.positional.push(arg.to_owned());
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(name)
+    }
+
+    /// Last value given for an option
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .get(name)
+            .and_then(|values| values.last())
+            .map(|value| value.as_str())
+    }
+
+    fn values(&self, name: &str) -> &[String] {
+        self.options.get(name).map_or(&[], |values| &values[..])
+    }
+
+    fn required(&self, name: &str) -> Result<&str> {
+        self.value(name)
+            .ok_or_else(|| format!("missing {}", name).into())
+    }
+
+    fn number(&self, name: &str) -> Result<Option<usize>> {
+        self.value(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("{} expects a number, got {}", name, value).into())
+            })
+            .transpose()
+    }
+
+    fn single_input(&self) -> Result<&str> {
+        match &self.positional[..] {
+            [input] => Ok(input),
+            _ => Err("expected exactly one input file".into()),
+        }
+    }
+}
+
+/// --format if given, the output extension otherwise
+fn output_format(args: &Args, path: &str) -> Result<Format> {
+    match args.value("--format") {
+        Some(name) => Ok(Format::from_name(name)?),
+        None => Ok(Format::from_path(path)),
+    }
+}
+
+/// --output file if given, stdout otherwise
+fn output(args: &Args) -> Result<Box<dyn Write>> {
+    Ok(match args.value("--output") {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    })
+}
+
+fn train<T: Element>(args: &Args) -> Result<()> {
+    let vocab_size = args.number("--vocab-size")?.ok_or("missing --vocab-size")?;
+    let max_length = args.number("--max-length")?.unwrap_or(usize::MAX);
+    let output = args.required("--output")?;
+    let format = output_format(args, output)?;
+    if args.positional.is_empty() {
+        return Err("no corpus files given".into());
+    }
+
+    let inputs = args
+        .positional
+        .iter()
+        .map(|path| T::read_input(path))
+        .collect::<Result<Vec<Vec<T>>>>()?;
+
+    let mut tokenizer = T::train(inputs, vocab_size, max_length);
+    for name in args.values("--special") {
+        tokenizer.add_special_token(name);
+    }
+
+    save(&tokenizer, output, format)?;
+    eprintln!(
+        "trained {} tokens ({} special) into {}",
+        tokenizer.vocabulary_size(),
+        tokenizer.special_tokens.len(),
+        output
+    );
+    Ok(())
+}
+
+fn encode<T: Element>(args: &Args) -> Result<()> {
+    let tokenizer: Tokenizer<T> = load(args.required("--tokenizer")?, args.flag("--unchecked"))?;
+    let input = T::read_input(args.single_input()?)?;
+
+    // tokenize panics on elements the trie does not know
+    if let Some(position) = input
+        .iter()
+        .position(|elem| !tokenizer.children.contains_key(elem))
+    {
+        return Err(format!(
+            "element {:?} at {} is not in the vocabulary",
+            input[position], position
+        )
+        .into());
+    }
+
+    let token_buffer = T::encode(&tokenizer, &input);
+
+    let mut writer = output(args)?;
+    write_ids(&token_buffer, args.flag("--binary"), &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn decode<T: Element>(args: &Args) -> Result<()> {
+    let tokenizer: Tokenizer<T> = load(args.required("--tokenizer")?, args.flag("--unchecked"))?;
+    let ids = read_ids(&fs::read(args.single_input()?)?, args.flag("--binary"))?;
+
+    // detokenize panics on unknown values
+    if let Some(token_value) = ids.iter().find(|token_value| {
+        !tokenizer.lookup.contains_key(token_value) && !tokenizer.is_special_token(**token_value)
+    }) {
+        return Err(format!("unknown token value {}", token_value).into());
+    }
+
+    let mut detokenized: Vec<T> = vec![];
+    tokenizer.detokenize(&ids, &mut detokenized);
+
+    let mut writer = output(args)?;
+    T::write_output(&detokenized, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn inspect<T: Element>(args: &Args) -> Result<()> {
+    // always unchecked, inspect reports the issues itself
+    let tokenizer: Tokenizer<T> = load(args.required("--tokenizer")?, true)?;
+
+    match args.value("--dump") {
+        Some(kind) => dump_vocabulary(&tokenizer, kind),
+        None => {
+            let mut writer = BufWriter::new(io::stdout());
+            print_stats(&tokenizer, &mut writer)?;
+            writer.flush()?;
+            Ok(())
+        }
+    }
+}
+
+fn dump_vocabulary<T>(tokenizer: &Tokenizer<T>, kind: &str) -> Result<()>
+where
+    T: Eq + Hash + Clone + Debug + RenderElement,
+{
+    let mut writer = BufWriter::new(io::stdout());
+    match kind {
+        "tsv" => dump::write_tsv(tokenizer, &mut writer)?,
+        "json" => dump::write_json(tokenizer, &mut writer)?,
+        _ => return Err(format!("unknown dump kind {}", kind).into()),
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_stats<T, W>(tokenizer: &Tokenizer<T>, writer: &mut W) -> Result<()>
+where
+    T: Eq + Hash + Clone + Debug,
+    W: Write,
+{
+    let mut lengths: BTreeMap<usize, usize> = BTreeMap::new();
+    for token in tokenizer.lookup.values() {
+        *lengths.entry(token.len()).or_insert(0) += 1;
+    }
+    let total_length: usize = tokenizer.lookup.values().map(|token| token.len()).sum();
+
+    let mut specials: Vec<(&String, &usize)> = tokenizer.special_tokens.iter().collect();
+    specials.sort_unstable_by_key(|(_, token_value)| **token_value);
+
+    writeln!(writer, "vocabulary size: {}", tokenizer.vocabulary_size())?;
+    writeln!(writer, "tokens: {}", tokenizer.lookup.len())?;
+    writeln!(
+        writer,
+        "base tokens: {}",
+        lengths.get(&1).copied().unwrap_or(0)
+    )?;
+    writeln!(
+        writer,
+        "longest token: {}",
+        lengths.keys().next_back().copied().unwrap_or(0)
+    )?;
+    if !tokenizer.lookup.is_empty() {
+        writeln!(
+            writer,
+            "mean token length: {:.2}",
+            total_length as f64 / tokenizer.lookup.len() as f64
+        )?;
+    }
+    writeln!(writer, "special tokens: {}", specials.len())?;
+    for (name, token_value) in specials {
+        writeln!(writer, "  {} {}", token_value, name)?;
+    }
+    writeln!(writer, "tokens by length:")?;
+    for (length, count) in lengths {
+        writeln!(writer, "  {} {}", length, count)?;
+    }
+
+    let report = tokenizer.validate();
+    if report.issues.is_empty() {
+        writeln!(writer, "validation: ok")?;
+    } else if report.is_valid() {
+        // only informational issues
+        writeln!(writer, "validation: ok, {}", report)?;
+    } else {
+        writeln!(writer, "validation: {}", report)?;
+    }
+    Ok(())
+}
+
+fn convert<T: Element>(args: &Args) -> Result<()> {
+    let (input, output) = match &args.positional[..] {
+        [input, output] => (input, output),
+        _ => return Err("convert expects an input and an output file".into()),
+    };
+    let format = output_format(args, output)?;
+
+    let tokenizer: Tokenizer<T> = load(input, args.flag("--unchecked"))?;
+    Ok(save(&tokenizer, output, format)?)
+}
+
+fn write_ids<W: Write>(ids: &[usize], binary: bool, writer: &mut W) -> Result<()> {
+    if binary {
+        for token_value in ids {
+            let token_value = u32::try_from(*token_value)
+                .map_err(|_| format!("token value {} does not fit u32", token_value))?;
+            writer.write_all(&token_value.to_le_bytes())?;
+        }
+    } else {
+        let text: Vec<String> = ids
+            .iter()
+            .map(|token_value| token_value.to_string())
+            .collect();
+        writeln!(writer, "{}", text.join(" "))?;
+    }
+    Ok(())
+}
+
+fn read_ids(content: &[u8], binary: bool) -> Result<Vec<usize>> {
+    if binary {
+        if content.len() % 4 != 0 {
+            return Err("binary ids length is not a multiple of 4".into());
+        }
+        Ok(content
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize)
+            .collect())
+    } else {
+        std::str::from_utf8(content)?
+            .split_whitespace()
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| format!("invalid token value {}", id).into())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_ids, write_ids, Args};
+
+    #[test]
+    fn parse_arguments() {
+        let args: Vec<String> = [
+            "--vocab-size",
+            "512",
+            "--special",
+            "<eos>",
+            "--binary",
+            "a.txt",
+            "--special",
+            "<pad>",
+            "b.txt",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let args = Args::parse(&args).unwrap();
+
+        assert_eq!(args.number("--vocab-size").unwrap(), Some(512));
+        assert_eq!(args.values("--special"), ["<eos>", "<pad>"]);
+        assert!(args.flag("--binary"));
+        assert_eq!(args.positional, ["a.txt", "b.txt"]);
+        assert!(args.required("--output").is_err());
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        let ids = vec![0, 7, 70_000];
+        for binary in [false, true] {
+            let mut buffer = vec![];
+            write_ids(&ids, binary, &mut buffer).unwrap();
+            assert_eq!(read_ids(&buffer, binary).unwrap(), ids);
+        }
+    }
+}
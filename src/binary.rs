@@ -0,0 +1,334 @@
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    io::{self, Cursor, Read, Write},
+};
+
+use crate::{error::Error, Merge, Tokenizer};
+
+pub(crate) const MAGIC: &[u8; 4] = b"BPET";
+/// Bumped only when an existing section changes meaning. New sections can be
+/// added without a bump, readers skip the ones they do not know.
+const VERSION: u16 = 1;
+
+const SECTION_VOCABULARY: u8 = 1;
+const SECTION_MERGES: u8 = 2;
+const SECTION_SPECIALS: u8 = 3;
+const SECTION_COUNTS: u8 = 4;
+
+/// Element types that can be stored in the binary format
+pub trait BinaryElement: Sized {
+    /// Stored in the header so a file is not loaded with the wrong element type
+    const TYPE_TAG: u8;
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! impl_binary_element {
+    ($type:ty, $tag:expr) => {
+        impl BinaryElement for $type {
+            const TYPE_TAG: u8 = $tag;
+
+            fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+                writer.write_all(&self.to_le_bytes())
+            }
+
+            fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+                let mut buffer = [0; std::mem::size_of::<$type>()];
+                reader.read_exact(&mut buffer)?;
+                Ok(<$type>::from_le_bytes(buffer))
+            }
+        }
+    };
+}
+
+impl_binary_element!(u8, 1);
+impl_binary_element!(u16, 2);
+impl_binary_element!(u32, 3);
+impl_binary_element!(u64, 4);
+
+impl BinaryElement for char {
+    const TYPE_TAG: u8 = 5;
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        (*self as u32).write_to(writer)
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let value = u32::read_from(reader)?;
+        char::from_u32(value)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid char value"))
+    }
+}
+
+/// Write a tokenizer in the compact binary format.
+///
+/// Layout: magic "BPET", version (u16), element type tag (u8), a reserved
+/// byte, then sections made of an id (u8), a payload length (u32) and the
+/// payload. Integers are little endian, token values are stored as u32.
+/// - vocabulary: count, then token value, length and elements for each token
+/// - merges: count, then token value, left and right token values
+/// - specials: count, then token value, name length and utf-8 name
+/// - counts: count, then token value and training count (u64)
+pub fn save_binary<T, W>(tokenizer: &Tokenizer<T>, writer: &mut W) -> io::Result<()>
+where
+    T: Eq + Hash + Clone + Debug + BinaryElement,
+    W: Write,
+{
+    writer.write_all(MAGIC)?;
+    VERSION.write_to(writer)?;
+    writer.write_all(&[T::TYPE_TAG, 0])?;
+
+    let mut token_values: Vec<usize> = tokenizer.lookup.keys().copied().collect();
+    token_values.sort_unstable();
+
+    let mut payload = vec![];
+    write_len(&mut payload, token_values.len())?;
+    for token_value in token_values {
+        let token = &tokenizer.lookup[&token_value];
+        write_len(&mut payload, token_value)?;
+        write_len(&mut payload, token.len())?;
+        for elem in token {
+            elem.write_to(&mut payload)?;
+        }
+    }
+    write_section(writer, SECTION_VOCABULARY, &payload)?;
+
+    let merges = tokenizer.merges();
+    let mut payload = vec![];
+    write_len(&mut payload, merges.len())?;
+    for merge in merges {
+        write_len(&mut payload, merge.token_value)?;
+        write_len(&mut payload, merge.left)?;
+        write_len(&mut payload, merge.right)?;
+    }
+    write_section(writer, SECTION_MERGES, &payload)?;
+
+    let mut specials: Vec<(&String, &usize)> = tokenizer.special_tokens.iter().collect();
+    specials.sort_unstable_by_key(|(_, token_value)| **token_value);
+
+    let mut payload = vec![];
+    write_len(&mut payload, specials.len())?;
+    for (name, token_value) in specials {
+        write_len(&mut payload, *token_value)?;
+        write_len(&mut payload, name.len())?;
+        payload.write_all(name.as_bytes())?;
+    }
+    write_section(writer, SECTION_SPECIALS, &payload)?;
+
+    let mut counts: Vec<(&usize, &usize)> = tokenizer.training_counts.iter().collect();
+    counts.sort_unstable();
+
+    let mut payload = vec![];
+    write_len(&mut payload, counts.len())?;
+    for (token_value, count) in counts {
+        write_len(&mut payload, *token_value)?;
+        (*count as u64).write_to(&mut payload)?;
+    }
+    write_section(writer, SECTION_COUNTS, &payload)?;
+
+    Ok(())
+}
+
+/// Read a tokenizer written by save_binary, the trie is rebuilt from the
+/// vocabulary and merges are checked against it. The result must pass
+/// Tokenizer::validate
+pub fn load_binary<T, R>(reader: &mut R) -> Result<Tokenizer<T>, Error>
+where
+    T: Eq + Hash + Clone + Debug + BinaryElement,
+    R: Read,
+{
+    let tokenizer = load_binary_unchecked(reader)?;
+    tokenizer.validate().into_result()?;
+    Ok(tokenizer)
+}
+
+/// Same as load_binary without Tokenizer::validate, for files known to be
+/// good or to inspect broken ones
+pub fn load_binary_unchecked<T, R>(reader: &mut R) -> Result<Tokenizer<T>, Error>
+where
+    T: Eq + Hash + Clone + Debug + BinaryElement,
+    R: Read,
+{
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(Error::Format("not a binary tokenizer file".to_string()));
+    }
+
+    let version = u16::read_from(reader)?;
+    if version > VERSION {
+        return Err(Error::Format(format!("unsupported version {}", version)));
+    }
+
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    if header[0] != T::TYPE_TAG {
+        return Err(Error::Format(format!(
+            "file holds element type {}, expected {}",
+            header[0],
+            T::TYPE_TAG
+        )));
+    }
+
+    let mut tokenizer = Tokenizer::default();
+    let mut has_vocabulary = false;
+    let mut merges = vec![];
+
+    while let Some((section, payload)) = read_section(reader)? {
+        let mut payload = Cursor::new(payload);
+        match section {
+            SECTION_VOCABULARY => {
+                has_vocabulary = true;
+                for _ in 0..read_len(&mut payload)? {
+                    let token_value = read_len(&mut payload)?;
+                    let len = read_len(&mut payload)?;
+                    let token = (0..len)
+                        .map(|_| T::read_from(&mut payload))
+                        .collect::<io::Result<Vec<T>>>()?;
+                    if token.is_empty() {
+                        return Err(Error::Format(format!("token {} is empty", token_value)));
+                    }
+                    tokenizer.register(&token, token_value);
+                }
+            }
+            SECTION_MERGES => {
+                for _ in 0..read_len(&mut payload)? {
+                    let token_value = read_len(&mut payload)?;
+                    let left = read_len(&mut payload)?;
+                    let right = read_len(&mut payload)?;
+                    merges.push(Merge {
+                        left,
+                        right,
+                        token_value,
+                    });
+                }
+            }
+            SECTION_SPECIALS => {
+                for _ in 0..read_len(&mut payload)? {
+                    let token_value = read_len(&mut payload)?;
+                    let len = read_len(&mut payload)?;
+                    let name = read_bytes(&mut payload, len)?;
+                    let name = String::from_utf8(name)
+                        .map_err(|_| Error::Format("special token name is not utf-8".to_string()))?;
+                    tokenizer.special_tokens.insert(name, token_value);
+                }
+            }
+            SECTION_COUNTS => {
+                for _ in 0..read_len(&mut payload)? {
+                    let token_value = read_len(&mut payload)?;
+                    let count = u64::read_from(&mut payload)? as usize;
+                    tokenizer.training_counts.insert(token_value, count);
+                }
+            }
+            // written by a newer version, safe to ignore
+            _ => (),
+        }
+    }
+
+    if !has_vocabulary {
+        return Err(Error::Format("missing vocabulary section".to_string()));
+    }
+
+    tokenizer.check_merges(&merges)?;
+
+    Ok(tokenizer)
+}
+
+fn write_len<W: Write>(writer: &mut W, value: usize) -> io::Result<()> {
+    let value = u32::try_from(value)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "value does not fit in u32"))?;
+    value.write_to(writer)
+}
+
+fn read_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    Ok(u32::read_from(reader)? as usize)
+}
+
+fn write_section<W: Write>(writer: &mut W, section: u8, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&[section])?;
+    write_len(writer, payload.len())?;
+    writer.write_all(payload)
+}
+
+/// None once the end of the input is reached between two sections
+fn read_section<R: Read>(reader: &mut R) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut section = [0; 1];
+    if reader.read(&mut section)? == 0 {
+        return Ok(None);
+    }
+
+    let len = read_len(reader)?;
+    Ok(Some((section[0], read_bytes(reader, len)?)))
+}
+
+/// Lengths come from the file, read what is there instead of allocating
+/// them up front
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "section is shorter than its length",
+        ));
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_binary, save_binary, write_section};
+    use crate::{generate, test_data::RAW_TEXT, Tokenizer};
+
+    fn sample() -> Tokenizer<char> {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 96);
+        tokenizer.add_special_token("<eos>");
+        tokenizer
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let tokenizer = sample();
+
+        let mut buffer = vec![];
+        save_binary(&tokenizer, &mut buffer).unwrap();
+        let loaded: Tokenizer<char> = load_binary(&mut &buffer[..]).unwrap();
+
+        assert_eq!(tokenizer.lookup, loaded.lookup);
+        assert_eq!(tokenizer.special_tokens, loaded.special_tokens);
+        assert_eq!(tokenizer.training_counts, loaded.training_counts);
+        assert_eq!(tokenizer.merges(), loaded.merges());
+
+        // loading as another element type is refused
+        assert!(load_binary::<u8, _>(&mut &buffer[..]).is_err());
+    }
+
+    #[test]
+    fn binary_forward_compatibility() {
+        let tokenizer = sample();
+
+        let mut buffer = vec![];
+        save_binary(&tokenizer, &mut buffer).unwrap();
+
+        // a section added by a later writer is skipped
+        let mut extended = buffer.clone();
+        write_section(&mut extended, 200, &[1, 2, 3, 4, 5]).unwrap();
+        let loaded: Tokenizer<char> = load_binary(&mut &extended[..]).unwrap();
+        assert_eq!(tokenizer.lookup, loaded.lookup);
+
+        // a newer version changing existing sections is not
+        let mut newer = buffer.clone();
+        newer[4] = 2;
+        assert!(load_binary::<char, _>(&mut &newer[..]).is_err());
+
+        // a length past the end of the input fails without allocating it
+        let mut truncated = buffer[..8].to_vec();
+        truncated.push(200);
+        truncated.extend_from_slice(&u32::MAX.to_le_bytes());
+        truncated.extend_from_slice(&[1, 2, 3]);
+        assert!(load_binary::<char, _>(&mut &truncated[..]).is_err());
+    }
+}
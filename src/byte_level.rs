@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+/// GPT-2 mapping from bytes to printable unicode characters, used by byte
+/// level vocabularies so every token can be written as a string. Printable
+/// latin-1 bytes map to themselves, the others to characters from U+0100.
+pub fn bytes_to_unicode() -> [char; 256] {
+    let mut table = ['\0'; 256];
+    let mut shifted = 0;
+
+    for byte in 0..=255u8 {
+        let printable = matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+        table[byte as usize] = if printable {
+            byte as char
+        } else {
+            shifted += 1;
+            char::from_u32(255 + shifted).unwrap()
+        };
+    }
+
+    table
+}
+
+/// Reverse of bytes_to_unicode
+pub fn unicode_to_bytes() -> HashMap<char, u8> {
+    bytes_to_unicode()
+        .iter()
+        .enumerate()
+        .map(|(byte, c)| (*c, byte as u8))
+        .collect()
+}
+
+/// Bytes of a token written with bytes_to_unicode, None if a character is
+/// not part of the mapping
+pub fn decode_token(token: &str, mapping: &HashMap<char, u8>) -> Option<Vec<u8>> {
+    token.chars().map(|c| mapping.get(&c).copied()).collect()
+}
+
+pub fn encode_token(token: &[u8], table: &[char; 256]) -> String {
+    token.iter().map(|byte| table[*byte as usize]).collect()
+}
+
+/// Split text the way the GPT-2 pre-tokenizer regex does:
+/// `'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+`
+pub fn pretokenize(text: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut start = 0;
+
+    while start < text.len() {
+        let end = match_word(&text[start..]) + start;
+        words.push(&text[start..end]);
+        start = end;
+    }
+
+    words
+}
+
+/// Length in bytes of the word at the start of text
+fn match_word(text: &str) -> usize {
+    for contraction in ["'s", "'t", "'re", "'ve", "'m", "'ll", "'d"] {
+        if text.starts_with(contraction) {
+            return contraction.len();
+        }
+    }
+
+    let mut chars = text.char_indices().peekable();
+    let (_, first) = chars.next().unwrap();
+
+    // optional space followed by a run of letters, numbers or other symbols
+    let (class_start, class_char) = if first == ' ' {
+        match chars.peek() {
+            Some((i, c)) if !c.is_whitespace() => (*i, *c),
+            _ => (0, first),
+        }
+    } else {
+        (0, first)
+    };
+
+    if !class_char.is_whitespace() {
+        let class = CharClass::of(class_char);
+        return text[class_start..]
+            .char_indices()
+            .find(|(_, c)| CharClass::of(*c) != class)
+            .map_or(text.len(), |(i, _)| class_start + i);
+    }
+
+    // whitespace, the last one is left to prefix the next word
+    let run_end = text
+        .char_indices()
+        .find(|(_, c)| !c.is_whitespace())
+        .map(|(i, _)| i);
+
+    match run_end {
+        None => text.len(),
+        Some(run_end) => {
+            let last = text[..run_end].char_indices().last().unwrap().0;
+            if last == 0 {
+                run_end
+            } else {
+                last
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum CharClass {
+    Letter,
+    Number,
+    Whitespace,
+    Other,
+}
+
+impl CharClass {
+    fn of(c: char) -> Self {
+        if c.is_alphabetic() {
+            CharClass::Letter
+        } else if c.is_numeric() {
+            CharClass::Number
+        } else if c.is_whitespace() {
+            CharClass::Whitespace
+        } else {
+            CharClass::Other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bytes_to_unicode, pretokenize, unicode_to_bytes};
+
+    #[test]
+    fn byte_mapping() {
+        let table = bytes_to_unicode();
+        assert_eq!(table[b'a' as usize], 'a');
+        assert_eq!(table[b' ' as usize], 'Ġ');
+        assert_eq!(table[b'\n' as usize], 'Ċ');
+        assert_eq!(unicode_to_bytes().len(), 256);
+    }
+
+    #[test]
+    fn gpt2_pretokenize() {
+        assert_eq!(
+            pretokenize("Hello world, it's 2024!!  ok\n"),
+            vec!["Hello", " world", ",", " it", "'s", " 2024", "!!", " ", " ok", "\n"]
+        );
+        assert_eq!(pretokenize("a   b"), vec!["a", "  ", " b"]);
+        assert_eq!(pretokenize("a \t x  "), vec!["a", " \t", " x", "  "]);
+        assert_eq!(pretokenize(" a"), vec![" a"]);
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, Node, Tokenizer};
+
+/// Marks a byte with no node in the root table
+pub(crate) const NO_NODE: u32 = u32::MAX;
+/// Marks a node that only leads to longer tokens
+pub(crate) const NO_TOKEN: u32 = u32::MAX;
+
+/// Tokenizer specialised for bytes. The first byte of a token is resolved with
+/// a dense 256 entry table, deeper levels with sorted child arrays shared by
+/// all nodes. Convert from a trained Tokenizer<u8> with ByteTokenizer::from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "UncheckedByteTokenizer")]
+pub struct ByteTokenizer {
+    /// node reached by each first byte, NO_NODE if no token starts with it
+    #[serde(with = "root_table")]
+    pub(crate) root: [u32; 256],
+    /// token value of each node, NO_TOKEN if the node is not a token
+    pub(crate) node_tokens: Vec<u32>,
+    /// children of node i are at child_offsets[i]..child_offsets[i + 1]
+    /// in child_labels and child_targets, sorted by label
+    pub(crate) child_offsets: Vec<u32>,
+    pub(crate) child_labels: Vec<u8>,
+    pub(crate) child_targets: Vec<u32>,
+    /// bytes of token t are lookup_bytes[lookup_offsets[t]..lookup_offsets[t + 1]],
+    /// an empty range means no such token
+    pub(crate) lookup_offsets: Vec<u32>,
+    pub(crate) lookup_bytes: Vec<u8>,
+    pub(crate) special_tokens: HashMap<String, usize>,
+}
+
+/// Deserialized arrays, checked by ByteTokenizer::try_from so that corrupt
+/// data is refused instead of panicking when the trie is walked
+#[derive(Deserialize)]
+struct UncheckedByteTokenizer {
+    #[serde(with = "root_table")]
+    root: [u32; 256],
+    node_tokens: Vec<u32>,
+    child_offsets: Vec<u32>,
+    child_labels: Vec<u8>,
+    child_targets: Vec<u32>,
+    lookup_offsets: Vec<u32>,
+    lookup_bytes: Vec<u8>,
+    #[serde(default)]
+    special_tokens: HashMap<String, usize>,
+}
+
+impl ByteTokenizer {
+    pub fn new(tokenizer: &Tokenizer<u8>) -> Self {
+        let mut byte_tokenizer = ByteTokenizer {
+            root: [NO_NODE; 256],
+            node_tokens: vec![],
+            child_offsets: vec![0],
+            child_labels: vec![],
+            child_targets: vec![],
+            lookup_offsets: vec![0],
+            lookup_bytes: vec![],
+            special_tokens: tokenizer.special_tokens.clone(),
+        };
+
+        // breadth first so that each node's children get contiguous indices
+        let mut queue: Vec<&Node<u8>> = vec![];
+        for byte in sorted_keys(&tokenizer.children) {
+            let node = &tokenizer.children[&byte];
+            byte_tokenizer.root[byte as usize] = queue.len() as u32;
+            queue.push(node);
+        }
+
+        let mut current = 0;
+        while current < queue.len() {
+            let node = queue[current];
+            byte_tokenizer
+                .node_tokens
+                .push(match tokenizer.ends_token(node) {
+                    true => token_to_u32(node.token_value),
+                    false => NO_TOKEN,
+                });
+
+            for byte in sorted_keys(&node.children) {
+                byte_tokenizer.child_labels.push(byte);
+                byte_tokenizer.child_targets.push(queue.len() as u32);
+                queue.push(&node.children[&byte]);
+            }
+            byte_tokenizer
+                .child_offsets
+                .push(byte_tokenizer.child_labels.len() as u32);
+
+            current += 1;
+        }
+
+        let max_token = tokenizer.lookup.keys().max().map_or(0, |max| max + 1);
+        for token_value in 0..max_token {
+            if let Some(bytes) = tokenizer.lookup.get(&token_value) {
+                byte_tokenizer.lookup_bytes.extend_from_slice(bytes);
+            }
+            byte_tokenizer
+                .lookup_offsets
+                .push(byte_tokenizer.lookup_bytes.len() as u32);
+        }
+
+        byte_tokenizer
+    }
+
+    /// Bytes of a token, None if the tokenizer has no such token
+    pub fn token_bytes(&self, token_value: usize) -> Option<&[u8]> {
+        let start = *self.lookup_offsets.get(token_value)? as usize;
+        let end = *self.lookup_offsets.get(token_value + 1)? as usize;
+
+        if start == end {
+            return None;
+        }
+        Some(&self.lookup_bytes[start..end])
+    }
+
+    /// From buffer find the longest token starting at pointer, move pointer
+    /// past it and return its value
+    pub fn find_longest(&self, buffer: &[u8], pointer: &mut usize) -> usize {
+        let mut node = self.root[buffer[*pointer] as usize];
+        if node == NO_NODE {
+            panic!("no child in tokenizer that matches");
+        }
+        let mut end = *pointer + 1;
+        let mut longest = None;
+
+        // keep going past nodes that are not tokens, then fall back to the last token
+        loop {
+            let token_value = self.node_tokens[node as usize];
+            if token_value != NO_TOKEN {
+                longest = Some((token_value, end));
+            }
+            let byte = match buffer.get(end) {
+                None => break,
+                Some(byte) => byte,
+            };
+
+            let start = self.child_offsets[node as usize] as usize;
+            let stop = self.child_offsets[node as usize + 1] as usize;
+            match self.child_labels[start..stop].binary_search(byte) {
+                Err(_) => break,
+                Ok(i) => {
+                    node = self.child_targets[start + i];
+                    end += 1;
+                }
+            }
+        }
+
+        match longest {
+            None => panic!("no token starts with {:?}", buffer[*pointer]),
+            Some((token_value, end)) => {
+                *pointer = end;
+                token_value as usize
+            }
+        }
+    }
+
+    pub fn tokenize(&self, read_buffer: &[u8], write_buffer: &mut Vec<usize>, pointer: &mut usize) {
+        while *pointer < read_buffer.len() {
+            write_buffer.push(self.find_longest(read_buffer, pointer));
+        }
+    }
+
+    /// Special tokens have no bytes and are skipped
+    pub fn detokenize(&self, read_buffer: &[usize], write_buffer: &mut Vec<u8>) {
+        for elem in read_buffer {
+            match self.token_bytes(*elem) {
+                Some(bytes) => write_buffer.extend_from_slice(bytes),
+                None if self.special_tokens.values().any(|value| value == elem) => (),
+                None => panic!("unknown token value {}", elem),
+            }
+        }
+    }
+
+    /// Rebuild the generic tokenizer
+    pub fn to_tokenizer(&self) -> Tokenizer<u8> {
+        let mut tokenizer = Tokenizer {
+            special_tokens: self.special_tokens.clone(),
+            ..Default::default()
+        };
+        // deserialized offsets may be empty
+        for token_value in 0..self.lookup_offsets.len().saturating_sub(1) {
+            if let Some(bytes) = self.token_bytes(token_value) {
+                tokenizer.register(bytes, token_value);
+            }
+        }
+
+        // intermediate nodes that are not tokens keep the value they had
+        // in the trie this was built from
+        for (byte, node) in self.root.iter().enumerate() {
+            if let Some(child) = tokenizer.children.get_mut(&(byte as u8)) {
+                if *node != NO_NODE {
+                    self.restore_intermediate(*node, child);
+                }
+            }
+        }
+
+        tokenizer
+    }
+
+    fn restore_intermediate(&self, node: u32, target: &mut Node<u8>) {
+        let mut stack = vec![(node, target)];
+        while let Some((node, target)) = stack.pop() {
+            target.token_value = match self.node_tokens[node as usize] {
+                NO_TOKEN => 0,
+                token_value => token_value as usize,
+            };
+
+            let start = self.child_offsets[node as usize] as usize;
+            let end = self.child_offsets[node as usize + 1] as usize;
+            for (byte, child) in target.children.iter_mut() {
+                // a lookup that disagrees with the trie keeps the registered value
+                if let Ok(i) = self.child_labels[start..end].binary_search(byte) {
+                    stack.push((self.child_targets[start + i], child));
+                }
+            }
+        }
+    }
+
+    /// Array lengths, offsets and node indices, so that walking the trie
+    /// stays in bounds
+    fn check(&self) -> Result<(), String> {
+        let node_count = self.node_tokens.len();
+        if self.child_offsets.len() != node_count + 1 {
+            return Err(format!(
+                "{} child offsets for {} nodes",
+                self.child_offsets.len(),
+                node_count
+            ));
+        }
+        if self.child_labels.len() != self.child_targets.len() {
+            return Err(format!(
+                "{} child labels for {} child targets",
+                self.child_labels.len(),
+                self.child_targets.len()
+            ));
+        }
+        check_offsets(&self.child_offsets, self.child_labels.len())
+            .map_err(|err| format!("child offsets {}", err))?;
+        check_offsets(&self.lookup_offsets, self.lookup_bytes.len())
+            .map_err(|err| format!("lookup offsets {}", err))?;
+
+        for range in self.child_offsets.windows(2) {
+            let labels = &self.child_labels[range[0] as usize..range[1] as usize];
+            if labels.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err("child labels are not sorted".to_string());
+            }
+        }
+
+        let roots = self.root.iter().filter(|node| **node != NO_NODE);
+        if let Some(node) = roots
+            .chain(&self.child_targets)
+            .find(|node| **node as usize >= node_count)
+        {
+            return Err(format!("node {} out of {} nodes", node, node_count));
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<UncheckedByteTokenizer> for ByteTokenizer {
+    type Error = Error;
+
+    fn try_from(unchecked: UncheckedByteTokenizer) -> Result<Self, Error> {
+        let byte_tokenizer = ByteTokenizer {
+            root: unchecked.root,
+            node_tokens: unchecked.node_tokens,
+            child_offsets: unchecked.child_offsets,
+            child_labels: unchecked.child_labels,
+            child_targets: unchecked.child_targets,
+            lookup_offsets: unchecked.lookup_offsets,
+            lookup_bytes: unchecked.lookup_bytes,
+            special_tokens: unchecked.special_tokens,
+        };
+        byte_tokenizer.check().map_err(Error::Format)?;
+        Ok(byte_tokenizer)
+    }
+}
+
+impl From<&Tokenizer<u8>> for ByteTokenizer {
+    fn from(tokenizer: &Tokenizer<u8>) -> Self {
+        ByteTokenizer::new(tokenizer)
+    }
+}
+
+impl From<&ByteTokenizer> for Tokenizer<u8> {
+    fn from(byte_tokenizer: &ByteTokenizer) -> Self {
+        byte_tokenizer.to_tokenizer()
+    }
+}
+
+fn sorted_keys(children: &HashMap<u8, Node<u8>>) -> Vec<u8> {
+    let mut keys: Vec<u8> = children.keys().copied().collect();
+    keys.sort_unstable();
+    keys
+}
+
+/// Offsets start at 0, never decrease and end at len
+fn check_offsets(offsets: &[u32], len: usize) -> Result<(), String> {
+    if offsets.first() != Some(&0) {
+        return Err("do not start at 0".to_string());
+    }
+    if offsets.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err("decrease".to_string());
+    }
+    if offsets.last() != Some(&(len as u32)) {
+        return Err(format!("do not end at {}", len));
+    }
+    Ok(())
+}
+
+fn token_to_u32(token_value: usize) -> u32 {
+    u32::try_from(token_value).expect("token value does not fit in u32")
+}
+
+/// serde only derives arrays up to 32 elements, go through a sequence
+mod root_table {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(root: &[u32; 256], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(root.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u32; 256], D::Error> {
+        let values: Vec<u32> = Vec::deserialize(deserializer)?;
+        let len = values.len();
+        values
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"256 root entries"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteTokenizer;
+    use crate::{generate, test_data::RAW_TEXT, Tokenizer};
+
+    #[test]
+    fn byte_tokenizer_round_trip() {
+        let bytes: Vec<u8> = RAW_TEXT.bytes().take(4000).collect();
+        let tokenizer = generate(&bytes, 160);
+        let byte_tokenizer = ByteTokenizer::from(&tokenizer);
+
+        let mut expected: Vec<usize> = vec![];
+        tokenizer.tokenize(&bytes, &mut expected, &mut 0);
+
+        let mut token_buffer: Vec<usize> = vec![];
+        byte_tokenizer.tokenize(&bytes, &mut token_buffer, &mut 0);
+        assert_eq!(expected, token_buffer);
+
+        let mut detokenized = vec![];
+        byte_tokenizer.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(bytes, detokenized);
+
+        // through serde and back to the generic tokenizer
+        let serialized = serde_json::to_string(&byte_tokenizer).unwrap();
+        let deserialized: ByteTokenizer = serde_json::from_str(&serialized).unwrap();
+        let rebuilt = deserialized.to_tokenizer();
+
+        assert_eq!(tokenizer.lookup, rebuilt.lookup);
+        let mut rebuilt_tokens: Vec<usize> = vec![];
+        rebuilt.tokenize(&bytes, &mut rebuilt_tokens, &mut 0);
+        assert_eq!(expected, rebuilt_tokens);
+
+        // no offsets at all
+        let mut empty = ByteTokenizer::new(&Tokenizer::default());
+        empty.lookup_offsets.clear();
+        assert!(empty.to_tokenizer().lookup.is_empty());
+
+        // corrupt arrays are refused on load
+        let mut corrupt = byte_tokenizer.clone();
+        corrupt.child_targets[0] = corrupt.node_tokens.len() as u32;
+        let serialized = serde_json::to_string(&corrupt).unwrap();
+        assert!(serde_json::from_str::<ByteTokenizer>(&serialized).is_err());
+        let mut corrupt = byte_tokenizer.clone();
+        corrupt.lookup_offsets.clear();
+        let serialized = serde_json::to_string(&corrupt).unwrap();
+        assert!(serde_json::from_str::<ByteTokenizer>(&serialized).is_err());
+    }
+
+    #[test]
+    fn keep_special_tokens() {
+        let bytes: Vec<u8> = RAW_TEXT.bytes().take(4000).collect();
+        let mut tokenizer = generate(&bytes, 160);
+        let eos = tokenizer.add_special_token("<eos>");
+        let byte_tokenizer = ByteTokenizer::from(&tokenizer);
+
+        let mut token_buffer: Vec<usize> = vec![];
+        byte_tokenizer.tokenize(&bytes[..100], &mut token_buffer, &mut 0);
+        token_buffer.push(eos);
+        let mut detokenized = vec![];
+        byte_tokenizer.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(bytes[..100], detokenized);
+
+        assert_eq!(
+            byte_tokenizer.to_tokenizer().special_token("<eos>"),
+            Some(eos)
+        );
+    }
+}
@@ -0,0 +1,228 @@
+use std::{collections::HashMap, fmt::Debug, hash::Hash, ops::Range};
+
+use crate::{Node, Tokenizer};
+
+/// Nodes with more children than this get a hashmap, the others are scanned linearly
+const LINEAR_SCAN_LIMIT: usize = 8;
+/// Marks a node that only leads to longer tokens
+const NO_TOKEN: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct CompiledNode {
+    token_value: usize,
+    /// index into edge_labels / edge_targets, or into wide if the node has
+    /// more than LINEAR_SCAN_LIMIT children
+    first_edge: u32,
+    edge_count: u32,
+}
+
+/// Read-only version of Tokenizer<T> with every node stored in a single arena.
+/// Children of a node are contiguous in the edge arrays, so encoding does not
+/// chase one HashMap per trie level. Produce it with Tokenizer::compile once
+/// training is done.
+#[derive(Debug, Clone)]
+pub struct CompiledTokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    /// index 0 is the root, its token value is meaningless
+    nodes: Vec<CompiledNode>,
+    edge_labels: Vec<T>,
+    edge_targets: Vec<u32>,
+    wide: Vec<HashMap<T, u32>>,
+    /// token value -> range in lookup_elements
+    lookup_spans: Vec<Option<Range<usize>>>,
+    lookup_elements: Vec<T>,
+}
+
+impl<T> CompiledTokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    pub fn new(tokenizer: &Tokenizer<T>) -> Self {
+        let mut compiled = CompiledTokenizer {
+            nodes: vec![],
+            edge_labels: vec![],
+            edge_targets: vec![],
+            wide: vec![],
+            lookup_spans: vec![],
+            lookup_elements: vec![],
+        };
+
+        // breadth first, nodes are pushed in the order they are processed so
+        // the children of a node can be given contiguous indices when it is popped
+        let mut queue: Vec<&HashMap<T, Node<T>>> = vec![&tokenizer.children];
+        compiled.nodes.push(CompiledNode {
+            token_value: 0,
+            first_edge: 0,
+            edge_count: 0,
+        });
+
+        let mut current = 0;
+        while current < queue.len() {
+            let children = queue[current];
+            let first_child = compiled.nodes.len() as u32;
+
+            let node = &mut compiled.nodes[current];
+            node.edge_count = children.len() as u32;
+            if children.len() > LINEAR_SCAN_LIMIT {
+                node.first_edge = compiled.wide.len() as u32;
+                compiled.wide.push(
+                    children
+                        .keys()
+                        .enumerate()
+                        .map(|(i, key)| (key.to_owned(), first_child + i as u32))
+                        .collect(),
+                );
+            } else {
+                node.first_edge = compiled.edge_labels.len() as u32;
+                for (i, key) in children.keys().enumerate() {
+                    compiled.edge_labels.push(key.to_owned());
+                    compiled.edge_targets.push(first_child + i as u32);
+                }
+            }
+
+            // keys and values iterate in the same order
+            for child in children.values() {
+                compiled.nodes.push(CompiledNode {
+                    token_value: match tokenizer.ends_token(child) {
+                        true => child.token_value,
+                        false => NO_TOKEN,
+                    },
+                    first_edge: 0,
+                    edge_count: 0,
+                });
+                queue.push(&child.children);
+            }
+
+            current += 1;
+        }
+
+        let max_token = tokenizer.lookup.keys().max().map_or(0, |max| max + 1);
+        compiled.lookup_spans = vec![None; max_token];
+        for (token_value, elements) in &tokenizer.lookup {
+            let start = compiled.lookup_elements.len();
+            compiled.lookup_elements.extend_from_slice(elements);
+            compiled.lookup_spans[*token_value] = Some(start..compiled.lookup_elements.len());
+        }
+
+        compiled
+    }
+
+    fn child(&self, node: u32, elem: &T) -> Option<u32> {
+        let node = &self.nodes[node as usize];
+        let first = node.first_edge as usize;
+        let count = node.edge_count as usize;
+
+        if count > LINEAR_SCAN_LIMIT {
+            return self.wide[first].get(elem).copied();
+        }
+
+        self.edge_labels[first..first + count]
+            .iter()
+            .position(|label| label == elem)
+            .map(|i| self.edge_targets[first + i])
+    }
+
+    /// From buffer find the longest token starting at pointer, move pointer
+    /// past it and return its value
+    pub fn find_longest(&self, buffer: &[T], pointer: &mut usize) -> usize {
+        let mut node = match self.child(0, &buffer[*pointer]) {
+            None => panic!("no child in tokenizer that matches"),
+            Some(node) => node,
+        };
+        let mut end = *pointer + 1;
+        let mut longest = None;
+
+        // keep going past nodes that are not tokens, then fall back to the last token
+        loop {
+            let token_value = self.nodes[node as usize].token_value;
+            if token_value != NO_TOKEN {
+                longest = Some((token_value, end));
+            }
+            match buffer.get(end).and_then(|elem| self.child(node, elem)) {
+                None => break,
+                Some(child) => {
+                    node = child;
+                    end += 1;
+                }
+            }
+        }
+
+        match longest {
+            None => panic!("no token starts with {:?}", buffer[*pointer]),
+            Some((token_value, end)) => {
+                *pointer = end;
+                token_value
+            }
+        }
+    }
+
+    pub fn tokenize(&self, read_buffer: &[T], write_buffer: &mut Vec<usize>, pointer: &mut usize) {
+        while *pointer < read_buffer.len() {
+            write_buffer.push(self.find_longest(read_buffer, pointer));
+        }
+    }
+
+    /// Special tokens and values without elements are skipped
+    pub fn detokenize(&self, read_buffer: &[usize], write_buffer: &mut Vec<T>) {
+        for elem in read_buffer {
+            if let Some(Some(span)) = self.lookup_spans.get(*elem) {
+                write_buffer.extend_from_slice(&self.lookup_elements[span.clone()]);
+            }
+        }
+    }
+
+    /// Number of trie nodes, root included
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+impl<T> From<&Tokenizer<T>> for CompiledTokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    fn from(tokenizer: &Tokenizer<T>) -> Self {
+        CompiledTokenizer::new(tokenizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate, test_data::RAW_TEXT};
+
+    #[test]
+    fn compiled_matches_tokenizer() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(4000).collect();
+        let tokenizer = generate(&text_val, 160);
+        let compiled = tokenizer.compile();
+
+        let mut expected: Vec<usize> = vec![];
+        tokenizer.tokenize(&text_val, &mut expected, &mut 0);
+
+        let mut token_buffer: Vec<usize> = vec![];
+        compiled.tokenize(&text_val, &mut token_buffer, &mut 0);
+        assert_eq!(expected, token_buffer);
+
+        let mut detokenized = vec![];
+        compiled.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(text_val, detokenized);
+    }
+
+    #[test]
+    fn skip_special_tokens() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(4000).collect();
+        let mut tokenizer = generate(&text_val, 160);
+        let eos = tokenizer.add_special_token("<eos>");
+        let compiled = tokenizer.compile();
+
+        let mut token_buffer: Vec<usize> = vec![];
+        compiled.tokenize(&text_val[..100], &mut token_buffer, &mut 0);
+        token_buffer.push(eos);
+
+        let mut detokenized = vec![];
+        compiled.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(text_val[..100], detokenized);
+    }
+}
@@ -0,0 +1,349 @@
+use std::{
+    fmt::Debug,
+    fs::{self, File},
+    hash::Hash,
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use memmap2::Mmap;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, Tokenizer};
+
+const INDEX_FILE: &str = "index.json";
+
+/// Documents encoded per parallel batch, bounds the ids held in memory
+const BATCH_DOCUMENTS: usize = 1024;
+
+/// Bytes per stored token value, little endian
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenWidth {
+    U16,
+    U32,
+}
+
+impl TokenWidth {
+    /// Smallest width that holds every token value of the tokenizer
+    pub fn for_tokenizer<T>(tokenizer: &Tokenizer<T>) -> Self
+    where
+        T: Eq + Hash + Clone + Debug,
+    {
+        let max = tokenizer
+            .lookup
+            .keys()
+            .chain(tokenizer.special_tokens.values())
+            .max()
+            .copied()
+            .unwrap_or(0);
+        if max <= u16::MAX as usize {
+            TokenWidth::U16
+        } else {
+            TokenWidth::U32
+        }
+    }
+
+    pub fn bytes(&self) -> usize {
+        match self {
+            TokenWidth::U16 => 2,
+            TokenWidth::U32 => 4,
+        }
+    }
+
+    pub(crate) fn write<W: Write>(&self, token_value: usize, writer: &mut W) -> Result<(), Error> {
+        match self {
+            TokenWidth::U16 => writer.write_all(&(token_value as u16).to_le_bytes())?,
+            TokenWidth::U32 => {
+                let token_value = u32::try_from(token_value).map_err(|_| {
+                    Error::Format(format!("token value {} does not fit u32", token_value))
+                })?;
+                writer.write_all(&token_value.to_le_bytes())?
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&self, bytes: &[u8]) -> usize {
+        match self {
+            TokenWidth::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+            TokenWidth::U32 => {
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize
+            }
+        }
+    }
+}
+
+/// Error::Format instead of the panic tokenize raises on elements outside
+/// the vocabulary
+pub(crate) fn check_elements<T>(tokenizer: &Tokenizer<T>, document: &[T]) -> Result<(), Error>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    match document
+        .iter()
+        .position(|elem| !tokenizer.children.contains_key(elem))
+    {
+        Some(position) => Err(Error::Format(format!(
+            "element {:?} at {} is not in the vocabulary",
+            document[position], position
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShardIndex {
+    /// File name inside the dataset directory
+    pub file: String,
+    /// Token offset of each document start, then the shard length
+    pub offsets: Vec<u64>,
+}
+
+/// Written next to the shards as index.json
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DatasetIndex {
+    pub width: TokenWidth,
+    pub shards: Vec<ShardIndex>,
+}
+
+/// Encode documents in parallel and write them to shards of at most
+/// shard_tokens tokens in directory. A document is never split, one longer
+/// than shard_tokens gets a shard of its own. Fails on elements outside the
+/// vocabulary before writing anything.
+pub fn write_dataset<T, P>(
+    tokenizer: &Tokenizer<T>,
+    documents: &[Vec<T>],
+    directory: P,
+    shard_tokens: usize,
+) -> Result<DatasetIndex, Error>
+where
+    T: Eq + Hash + Clone + Debug + Sync,
+    P: AsRef<Path>,
+{
+    documents
+        .par_iter()
+        .try_for_each(|document| check_elements(tokenizer, document))?;
+
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+
+    let width = TokenWidth::for_tokenizer(tokenizer);
+    let mut index = DatasetIndex {
+        width,
+        shards: vec![],
+    };
+    let mut writer: Option<BufWriter<File>> = None;
+
+    for batch in documents.chunks(BATCH_DOCUMENTS) {
+        let encoded: Vec<Vec<usize>> = batch
+            .par_iter()
+            .map(|document| {
+                let mut token_buffer: Vec<usize> = vec![];
+                tokenizer.tokenize(document, &mut token_buffer, &mut 0);
+                token_buffer
+            })
+            .collect();
+
+        for token_buffer in encoded {
+            let shard_len = index
+                .shards
+                .last()
+                .and_then(|shard| shard.offsets.last())
+                .copied();
+            let is_full = match shard_len {
+                Some(len) => len > 0 && len as usize + token_buffer.len() > shard_tokens,
+                None => true,
+            };
+
+            if is_full {
+                if let Some(mut full) = writer.take() {
+                    full.flush()?;
+                }
+                let file = format!("shard_{:05}.bin", index.shards.len());
+                writer = Some(BufWriter::new(File::create(directory.join(&file))?));
+                index.shards.push(ShardIndex {
+                    file,
+                    offsets: vec![0],
+                });
+            }
+
+            let shard = index.shards.last_mut().unwrap();
+            let writer = writer.as_mut().unwrap();
+            for token_value in &token_buffer {
+                width.write(*token_value, writer)?;
+            }
+            let end = shard.offsets.last().unwrap() + token_buffer.len() as u64;
+            shard.offsets.push(end);
+        }
+    }
+    if let Some(mut writer) = writer {
+        writer.flush()?;
+    }
+
+    fs::write(directory.join(INDEX_FILE), serde_json::to_string(&index)?)?;
+    Ok(index)
+}
+
+/// Shards written by write_dataset, memory mapped so many readers share them
+#[derive(Debug)]
+pub struct Dataset {
+    pub index: DatasetIndex,
+    shards: Vec<Mmap>,
+    /// Token offset of each shard in the whole dataset
+    shard_starts: Vec<usize>,
+}
+
+impl Dataset {
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<Self, Error> {
+        let directory: PathBuf = directory.as_ref().to_owned();
+        let index: DatasetIndex =
+            serde_json::from_str(&fs::read_to_string(directory.join(INDEX_FILE))?)?;
+
+        let mut shards = vec![];
+        let mut shard_starts = vec![];
+        let mut start = 0;
+        for shard in &index.shards {
+            let file = File::open(directory.join(&shard.file))?;
+            // SAFETY: the map is read only, shards must not be truncated while in use
+            let map = unsafe { Mmap::map(&file)? };
+
+            let len = *shard.offsets.last().unwrap_or(&0) as usize;
+            if map.len() != len * index.width.bytes() {
+                return Err(Error::Format(format!(
+                    "{} holds {} bytes, index expects {} tokens",
+                    shard.file,
+                    map.len(),
+                    len
+                )));
+            }
+            shards.push(map);
+            shard_starts.push(start);
+            start += len;
+        }
+        shard_starts.push(start);
+
+        Ok(Dataset {
+            index,
+            shards,
+            shard_starts,
+        })
+    }
+
+    pub fn token_count(&self) -> usize {
+        *self.shard_starts.last().unwrap()
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.index
+            .shards
+            .iter()
+            .map(|shard| shard.offsets.len().saturating_sub(1))
+            .sum()
+    }
+
+    /// Token values in range, across shard boundaries
+    pub fn tokens(&self, range: Range<usize>) -> Vec<usize> {
+        assert!(
+            range.end <= self.token_count(),
+            "range past the end of the dataset"
+        );
+
+        let width = self.index.width.bytes();
+        let mut tokens = Vec::with_capacity(range.len());
+        let mut position = range.start;
+        while position < range.end {
+            // last shard starting at or before position
+            let shard = self
+                .shard_starts
+                .partition_point(|start| *start <= position)
+                - 1;
+            let end = range.end.min(self.shard_starts[shard + 1]);
+            let local = position - self.shard_starts[shard];
+            let bytes = &self.shards[shard][local * width..(local + end - position) * width];
+            tokens.extend(
+                bytes
+                    .chunks_exact(width)
+                    .map(|chunk| self.index.width.read(chunk)),
+            );
+            position = end;
+        }
+        tokens
+    }
+
+    /// Every document in order
+    pub fn documents(&self) -> impl Iterator<Item = Vec<usize>> + '_ {
+        self.index
+            .shards
+            .iter()
+            .zip(&self.shard_starts)
+            .flat_map(move |(shard, start)| {
+                shard.offsets.windows(2).map(move |bounds| {
+                    self.tokens(start + bounds[0] as usize..start + bounds[1] as usize)
+                })
+            })
+    }
+
+    /// Windows of length tokens every stride tokens over the concatenated
+    /// documents, the incomplete tail is dropped
+    pub fn windows(&self, length: usize, stride: usize) -> impl Iterator<Item = Vec<usize>> + '_ {
+        assert!(
+            length > 0 && stride > 0,
+            "length and stride must be above 0"
+        );
+        let count = match self.token_count() {
+            total if total >= length => (total - length) / stride + 1,
+            _ => 0,
+        };
+        (0..count).map(move |window| self.tokens(window * stride..window * stride + length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_dataset, Dataset, TokenWidth};
+    use crate::{error::Error, generate, temp_path::TempPath, test_data::RAW_TEXT};
+
+    #[test]
+    fn write_and_read_shards() {
+        let documents: Vec<Vec<char>> = RAW_TEXT
+            .lines()
+            .filter(|line| !line.is_empty())
+            .take(20)
+            .map(|line| line.chars().collect())
+            .collect();
+        let tokenizer = generate(&documents.concat(), 96);
+
+        let directory = TempPath::new("bpe_dataset");
+        let unknown = vec![vec!['\u{1F3B5}']];
+        assert!(matches!(
+            write_dataset(&tokenizer, &unknown, &directory, 400),
+            Err(Error::Format(_))
+        ));
+        assert!(!directory.as_ref().exists());
+
+        let index = write_dataset(&tokenizer, &documents, &directory, 400).unwrap();
+        assert_eq!(index.width, TokenWidth::U16);
+        assert!(index.shards.len() > 1);
+
+        let dataset = Dataset::open(&directory).unwrap();
+        assert_eq!(dataset.document_count(), documents.len());
+
+        let mut all_tokens = vec![];
+        for (document, token_buffer) in documents.iter().zip(dataset.documents()) {
+            let mut expected: Vec<usize> = vec![];
+            tokenizer.tokenize(document, &mut expected, &mut 0);
+            assert_eq!(token_buffer, expected);
+            all_tokens.extend(token_buffer);
+        }
+        assert_eq!(dataset.token_count(), all_tokens.len());
+
+        // windows cross document and shard boundaries
+        let windows: Vec<Vec<usize>> = dataset.windows(64, 48).collect();
+        assert_eq!(windows.len(), (all_tokens.len() - 64) / 48 + 1);
+        for (i, window) in windows.iter().enumerate() {
+            assert_eq!(window[..], all_tokens[i * 48..i * 48 + 64]);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fmt::{Debug, Write as _},
+    hash::Hash,
+    io::{self, Write},
+};
+
+use serde::Serialize;
+
+use crate::{error::Error, Tokenizer};
+
+/// Printable form of an element, used to inspect a vocabulary
+pub trait RenderElement {
+    /// Written between two elements of the same token
+    const SEPARATOR: &'static str = "";
+
+    /// Append the element to out, escaping anything that is not printable
+    fn render(&self, out: &mut String);
+}
+
+impl RenderElement for char {
+    fn render(&self, out: &mut String) {
+        match self {
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => write!(out, "\\u{{{:x}}}", *c as u32).unwrap(),
+            c => out.push(*c),
+        }
+    }
+}
+
+impl RenderElement for u8 {
+    fn render(&self, out: &mut String) {
+        match self {
+            b'\\' => out.push_str("\\\\"),
+            b' '..=b'~' => out.push(*self as char),
+            _ => write!(out, "\\x{:02x}", self).unwrap(),
+        }
+    }
+}
+
+macro_rules! impl_render_hex {
+    ($type:ty, $width:expr) => {
+        impl RenderElement for $type {
+            const SEPARATOR: &'static str = " ";
+
+            fn render(&self, out: &mut String) {
+                write!(out, "{:#0width$x}", self, width = $width + 2).unwrap()
+            }
+        }
+    };
+}
+
+impl_render_hex!(u16, 4);
+impl_render_hex!(u32, 8);
+impl_render_hex!(u64, 16);
+
+pub fn render_token<T: RenderElement>(token: &[T]) -> String {
+    let mut out = String::new();
+    for (i, elem) in token.iter().enumerate() {
+        if i > 0 {
+            out.push_str(T::SEPARATOR);
+        }
+        elem.render(&mut out);
+    }
+    out
+}
+
+/// One line of a vocabulary dump
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct VocabularyRow {
+    pub token_value: usize,
+    /// special tokens show their name, escaped like tokens of chars
+    pub token: String,
+    /// number of elements, 0 for special tokens
+    pub length: usize,
+    pub training_count: Option<usize>,
+    pub special: bool,
+}
+
+/// Every token with its rendering, ordered by token value
+pub fn vocabulary_rows<T>(tokenizer: &Tokenizer<T>) -> Vec<VocabularyRow>
+where
+    T: Eq + Hash + Clone + Debug + RenderElement,
+{
+    let mut rows: Vec<VocabularyRow> = tokenizer
+        .lookup
+        .iter()
+        .map(|(token_value, token)| VocabularyRow {
+            token_value: *token_value,
+            token: render_token(token),
+            length: token.len(),
+            training_count: tokenizer.training_counts.get(token_value).copied(),
+            special: false,
+        })
+        .chain(
+            tokenizer
+                .special_tokens
+                .iter()
+                .map(|(name, token_value)| VocabularyRow {
+                    token_value: *token_value,
+                    token: render_token(&name.chars().collect::<Vec<char>>()),
+                    length: 0,
+                    training_count: None,
+                    special: true,
+                }),
+        )
+        .collect();
+
+    rows.sort_unstable_by_key(|row| row.token_value);
+    rows
+}
+
+/// Tab separated dump with a header line, unknown training counts are empty
+pub fn write_tsv<T, W>(tokenizer: &Tokenizer<T>, writer: &mut W) -> io::Result<()>
+where
+    T: Eq + Hash + Clone + Debug + RenderElement,
+    W: Write,
+{
+    writeln!(writer, "id\ttoken\tlength\ttraining_count")?;
+    for row in vocabulary_rows(tokenizer) {
+        let count = row.training_count.map_or(String::new(), |c| c.to_string());
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            row.token_value, row.token, row.length, count
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_json<T, W>(tokenizer: &Tokenizer<T>, writer: &mut W) -> Result<(), Error>
+where
+    T: Eq + Hash + Clone + Debug + RenderElement,
+    W: Write,
+{
+    serde_json::to_writer_pretty(writer, &vocabulary_rows(tokenizer))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_token, vocabulary_rows, write_tsv};
+    use crate::{generate, test_data::RAW_TEXT};
+
+    #[test]
+    fn render_elements() {
+        assert_eq!(render_token(&['a', '\n', '\t', '\u{7}']), "a\\n\\t\\u{7}");
+        assert_eq!(render_token(b"ok\x00\xff\\"), "ok\\x00\\xff\\\\");
+        assert_eq!(render_token(&[0x12u16, 0xBEEF]), "0x0012 0xbeef");
+    }
+
+    #[test]
+    fn dump_vocabulary() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 64);
+        let eos = tokenizer.add_special_token("<eos>");
+        let tab = tokenizer.add_special_token("<a\tb>");
+
+        let rows = vocabulary_rows(&tokenizer);
+        assert_eq!(rows.len(), 66);
+        assert!(rows
+            .iter()
+            .all(|row| row.special || row.training_count.is_some()));
+        assert_eq!(rows[eos].token, "<eos>");
+        assert_eq!(rows[tab].token, "<a\\tb>");
+
+        let mut buffer = vec![];
+        write_tsv(&tokenizer, &mut buffer).unwrap();
+        let tsv = String::from_utf8(buffer).unwrap();
+        assert_eq!(tsv.lines().count(), 67);
+        // newlines and tabs are escaped, every line has four columns
+        assert!(tsv.lines().all(|line| line.split('\t').count() == 4));
+    }
+}
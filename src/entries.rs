@@ -0,0 +1,203 @@
+//! Serde helpers writing the maps of Tokenizer<T> as sequences, so element
+//! types that cannot be map keys in a format (tuples, structs in json) still
+//! serialize. Human readable formats also accept the older map layout.
+
+use std::{collections::HashMap, fmt, hash::Hash, marker::PhantomData};
+
+use serde::{
+    de::{IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::Node;
+
+/// Children are written as a list of nodes, keys come back from byte_value
+pub(crate) mod children {
+    use super::*;
+
+    pub fn serialize<T, S>(children: &HashMap<T, Node<T>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Eq + Hash + Clone + fmt::Debug + Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(children.values())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<HashMap<T, Node<T>>, D::Error>
+    where
+        T: Eq + Hash + Clone + fmt::Debug + Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let visitor = ChildrenVisitor(PhantomData);
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(visitor)
+        } else {
+            deserializer.deserialize_seq(visitor)
+        }
+    }
+
+    struct ChildrenVisitor<T>(PhantomData<T>);
+
+    impl<'de, T> Visitor<'de> for ChildrenVisitor<T>
+    where
+        T: Eq + Hash + Clone + fmt::Debug + Deserialize<'de>,
+    {
+        type Value = HashMap<T, Node<T>>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list of nodes")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut children = HashMap::new();
+            while let Some(node) = seq.next_element::<Node<T>>()? {
+                children.insert(node.byte_value.to_owned(), node);
+            }
+            Ok(children)
+        }
+
+        /// older layout, keyed by byte_value
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut children = HashMap::new();
+            while let Some((_, node)) = map.next_entry::<IgnoredAny, Node<T>>()? {
+                children.insert(node.byte_value.to_owned(), node);
+            }
+            Ok(children)
+        }
+    }
+}
+
+/// Lookup is written as a list of (token value, elements) pairs
+pub(crate) mod lookup {
+    use super::*;
+
+    pub fn serialize<T, S>(
+        lookup: &HashMap<usize, Vec<T>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        let mut entries: Vec<(&usize, &Vec<T>)> = lookup.iter().collect();
+        entries.sort_unstable_by_key(|(token_value, _)| **token_value);
+        serializer.collect_seq(entries)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<HashMap<usize, Vec<T>>, D::Error>
+    where
+        T: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let visitor = LookupVisitor(PhantomData);
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(visitor)
+        } else {
+            deserializer.deserialize_seq(visitor)
+        }
+    }
+
+    struct LookupVisitor<T>(PhantomData<T>);
+
+    impl<'de, T> Visitor<'de> for LookupVisitor<T>
+    where
+        T: Deserialize<'de>,
+    {
+        type Value = HashMap<usize, Vec<T>>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a list of token value and elements pairs")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut lookup = HashMap::new();
+            while let Some((token_value, token)) = seq.next_element::<(usize, Vec<T>)>()? {
+                lookup.insert(token_value, token);
+            }
+            Ok(lookup)
+        }
+
+        /// older layout, keyed by token value
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut lookup = HashMap::new();
+            while let Some((token_value, token)) = map.next_entry::<usize, Vec<T>>()? {
+                lookup.insert(token_value, token);
+            }
+            Ok(lookup)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt::Debug, hash::Hash};
+
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+    use crate::{generate, test_data::RAW_TEXT, Tokenizer};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+    struct Register {
+        channel: u8,
+        value: u16,
+    }
+
+    fn assert_round_trip<T>(input: Vec<T>)
+    where
+        T: Eq + Hash + Clone + Debug + Serialize + DeserializeOwned,
+    {
+        let tokenizer = generate(&input, 48);
+
+        let serialized = serde_json::to_string(&tokenizer).unwrap();
+        let loaded: Tokenizer<T> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(tokenizer.lookup, loaded.lookup);
+
+        let mut expected = vec![];
+        tokenizer.tokenize(&input, &mut expected, &mut 0);
+        let mut token_buffer = vec![];
+        loaded.tokenize(&input, &mut token_buffer, &mut 0);
+        assert_eq!(expected, token_buffer);
+    }
+
+    #[test]
+    fn serialize_any_element_type() {
+        let bytes: Vec<u8> = RAW_TEXT.bytes().take(1000).collect();
+
+        assert_round_trip(bytes.clone());
+        assert_round_trip(bytes.iter().map(|b| *b as u16 * 3).collect::<Vec<u16>>());
+        assert_round_trip(
+            bytes
+                .windows(2)
+                .map(|w| (w[0], w[1] % 4))
+                .collect::<Vec<(u8, u8)>>(),
+        );
+        assert_round_trip(
+            bytes
+                .iter()
+                .map(|b| Register {
+                    channel: b % 3,
+                    value: *b as u16,
+                })
+                .collect::<Vec<Register>>(),
+        );
+    }
+
+    #[test]
+    fn load_map_layout() {
+        // sample written before the switch to sequences
+        let tokenizer: Tokenizer<char> =
+            serde_json::from_str(include_str!("../tokenizer.json")).unwrap();
+        assert_eq!(tokenizer.lookup.len(), 512);
+
+        let serialized = serde_json::to_string(&tokenizer).unwrap();
+        let loaded: Tokenizer<char> = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(tokenizer.lookup, loaded.lookup);
+
+        let text_val: Vec<char> = RAW_TEXT.chars().collect();
+        let mut expected = vec![];
+        tokenizer.tokenize(&text_val, &mut expected, &mut 0);
+        let mut token_buffer = vec![];
+        loaded.tokenize(&text_val, &mut token_buffer, &mut 0);
+        assert_eq!(expected, token_buffer);
+    }
+}
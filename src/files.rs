@@ -0,0 +1,158 @@
+use std::{
+    fmt::Debug,
+    fs,
+    hash::Hash,
+    io::{BufWriter, Write},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    binary::{load_binary, load_binary_unchecked, save_binary, BinaryElement, MAGIC},
+    error::Error,
+    vocabulary::Vocabulary,
+    Tokenizer,
+};
+
+/// Formats a tokenizer file can be saved in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// serde layout of Tokenizer, trie included
+    Json,
+    /// vocabulary::Vocabulary as json
+    Vocabulary,
+    /// binary::save_binary
+    Binary,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Result<Self, Error> {
+        match name {
+            "json" => Ok(Format::Json),
+            "vocabulary" => Ok(Format::Vocabulary),
+            "binary" => Ok(Format::Binary),
+            _ => Err(Error::Format(format!("unknown format {}", name))),
+        }
+    }
+
+    /// .bin for binary, .vocab.json for vocabulary, json otherwise
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".bin") {
+            Format::Binary
+        } else if path.ends_with(".vocab.json") {
+            Format::Vocabulary
+        } else {
+            Format::Json
+        }
+    }
+}
+
+pub fn save<T>(tokenizer: &Tokenizer<T>, path: &str, format: Format) -> Result<(), Error>
+where
+    T: Eq + Hash + Clone + Debug + BinaryElement + Serialize,
+{
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    match format {
+        Format::Json => serde_json::to_writer(&mut writer, tokenizer)?,
+        Format::Vocabulary => {
+            serde_json::to_writer_pretty(&mut writer, &Vocabulary::from(tokenizer))?
+        }
+        Format::Binary => save_binary(tokenizer, &mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Load a tokenizer in any format, telling them apart by content
+pub fn load<T>(path: &str, unchecked: bool) -> Result<Tokenizer<T>, Error>
+where
+    T: Eq + Hash + Clone + Debug + BinaryElement + DeserializeOwned,
+{
+    let content = fs::read(path)?;
+    if content.starts_with(MAGIC) {
+        let mut reader = &content[..];
+        return match unchecked {
+            true => load_binary_unchecked(&mut reader),
+            false => load_binary(&mut reader),
+        };
+    }
+
+    let text =
+        String::from_utf8(content).map_err(|_| Error::Format(format!("{} is not utf-8", path)))?;
+    let value: serde_json::Value = serde_json::from_str(&text)?;
+    if value.get("tokens").is_some() {
+        let vocabulary: Vocabulary<T> = serde_json::from_value(value)?;
+        match unchecked {
+            true => vocabulary.to_tokenizer_unchecked(),
+            false => vocabulary.to_tokenizer(),
+        }
+    } else {
+        match unchecked {
+            true => Tokenizer::from_json_unchecked(&text),
+            false => Tokenizer::from_json(&text),
+        }
+    }
+}
+
+/// Tokenizer file holding bytes, judged from the header of binary files and
+/// from the first token element of json ones, a number rather than a string
+pub fn is_byte_tokenizer(path: &str) -> bool {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(_) => return false,
+    };
+    if content.starts_with(MAGIC) {
+        return content.get(6) == Some(&<u8 as BinaryElement>::TYPE_TAG);
+    }
+
+    serde_json::from_slice::<serde_json::Value>(&content)
+        .ok()
+        .as_ref()
+        .and_then(first_element)
+        .is_some_and(|elem| elem.is_number())
+}
+
+/// First element of the first token, in the vocabulary layout or in either
+/// lookup layout of the serde one
+fn first_element(value: &serde_json::Value) -> Option<&serde_json::Value> {
+    if let Some(tokens) = value.get("tokens") {
+        return tokens.get(0)?.get("elements")?.get(0);
+    }
+    match value.get("lookup")? {
+        serde_json::Value::Array(entries) => entries.first()?.get(1)?.get(0),
+        serde_json::Value::Object(entries) => entries.values().next()?.get(0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{is_byte_tokenizer, load, save, Format};
+    use crate::{generate, temp_path::TempPath, test_data::RAW_TEXT, Tokenizer};
+
+    #[test]
+    fn save_load_every_format() {
+        let bytes: Vec<u8> = RAW_TEXT.bytes().take(2000).collect();
+        let mut tokenizer = generate(&bytes, 96);
+        tokenizer.add_special_token("<eos>");
+
+        let directory = TempPath::new("bpe_files");
+        fs::create_dir_all(&directory).unwrap();
+        for name in ["tokenizer.json", "tokenizer.vocab.json", "tokenizer.bin"] {
+            let path = directory.as_ref().join(name);
+            let path = path.to_str().unwrap();
+            save(&tokenizer, path, Format::from_path(path)).unwrap();
+
+            let loaded: Tokenizer<u8> = load(path, false).unwrap();
+            assert_eq!(tokenizer.lookup, loaded.lookup);
+            assert_eq!(tokenizer.special_tokens, loaded.special_tokens);
+            assert!(is_byte_tokenizer(path));
+            assert!(load::<char>(path, false).is_err());
+        }
+
+        assert_eq!(Format::from_path("out.vocab.json"), Format::Vocabulary);
+        assert!(Format::from_name("yaml").is_err());
+    }
+}
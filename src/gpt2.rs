@@ -0,0 +1,160 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    byte_level::{bytes_to_unicode, decode_token, encode_token, unicode_to_bytes},
+    error::Error,
+    huggingface::{ByteLevel, HuggingFaceBpe},
+};
+
+const MERGES_HEADER: &str = "#version: 0.2";
+
+/// Read a GPT-2 style vocab.json and merges.txt pair as a byte level model.
+/// Entries of vocab.json named in special_tokens, such as "<|endoftext|>",
+/// become special tokens.
+pub fn load_gpt2(
+    vocab_json: &str,
+    merges_txt: &str,
+    special_tokens: &[&str],
+) -> Result<HuggingFaceBpe<u8>, Error> {
+    let vocab: HashMap<String, usize> = serde_json::from_str(vocab_json)?;
+
+    let specials = special_tokens
+        .iter()
+        .map(|name| match vocab.get(*name) {
+            None => Err(Error::Format(format!(
+                "special token {} not in vocab",
+                name
+            ))),
+            Some(token_value) => Ok((name.to_string(), *token_value)),
+        })
+        .collect::<Result<HashMap<String, usize>, Error>>()?;
+
+    let merges = merges_txt
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with("#version"))
+        .map(|line| {
+            line.split_once(' ')
+                .ok_or_else(|| Error::Format(format!("invalid merge {:?}", line)))
+        })
+        .collect::<Result<Vec<(&str, &str)>, Error>>()?;
+
+    let mapping = unicode_to_bytes();
+    let mut model = HuggingFaceBpe::from_strings(&vocab, &merges, &specials, |token| {
+        decode_token(token, &mapping)
+    })?;
+    model.byte_level = Some(ByteLevel {
+        add_prefix_space: false,
+        use_regex: true,
+    });
+
+    Ok(model)
+}
+
+/// Write a model as vocab.json and merges.txt contents. Special tokens are
+/// written to vocab.json under their name, which must not be the rendering
+/// of a regular token.
+pub fn save_gpt2(model: &HuggingFaceBpe<u8>) -> Result<(String, String), Error> {
+    let table = bytes_to_unicode();
+    let (mut vocab, merges) = model.to_strings(|token| encode_token(token, &table));
+
+    for (name, token_value) in &model.tokenizer.special_tokens {
+        if let Some(existing) = vocab.insert(name.to_owned(), *token_value) {
+            return Err(Error::Format(format!(
+                "special token {:?} has the same name as token {}",
+                name, existing
+            )));
+        }
+    }
+
+    let mut merges_txt = String::from(MERGES_HEADER);
+    merges_txt.push('\n');
+    for (left, right) in merges {
+        merges_txt.push_str(&format!("{} {}\n", left, right));
+    }
+
+    Ok((serde_json::to_string(&vocab)?, merges_txt))
+}
+
+pub fn load_gpt2_files<P: AsRef<Path>>(
+    vocab_path: P,
+    merges_path: P,
+    special_tokens: &[&str],
+) -> Result<HuggingFaceBpe<u8>, Error> {
+    load_gpt2(
+        &fs::read_to_string(vocab_path)?,
+        &fs::read_to_string(merges_path)?,
+        special_tokens,
+    )
+}
+
+pub fn save_gpt2_files<P: AsRef<Path>>(
+    model: &HuggingFaceBpe<u8>,
+    vocab_path: P,
+    merges_path: P,
+) -> Result<(), Error> {
+    let (vocab_json, merges_txt) = save_gpt2(model)?;
+    fs::write(vocab_path, vocab_json)?;
+    fs::write(merges_path, merges_txt)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_gpt2, save_gpt2};
+    use crate::{generate, huggingface::HuggingFaceBpe, test_data::RAW_TEXT};
+
+    const VOCAB: &str = r#"{"h": 0, "e": 1, "l": 2, "o": 3, "Ġ": 4, "w": 5, "r": 6, "d": 7,
+        "he": 8, "ll": 9, "llo": 10, "hello": 11, "Ġw": 12, "or": 13, "Ġwor": 14, "ld": 15,
+        "<|endoftext|>": 16}"#;
+    const MERGES: &str = "#version: 0.2\nh e\nl l\nll o\nhe llo\nĠ w\no r\nĠw or\nl d\n";
+
+    #[test]
+    fn gpt2_files() {
+        let model = load_gpt2(VOCAB, MERGES, &["<|endoftext|>"]).unwrap();
+        assert_eq!(model.tokenizer.special_token("<|endoftext|>"), Some(16));
+        assert_eq!(
+            model.encode_text("hello world<|endoftext|>"),
+            vec![11, 14, 15, 16]
+        );
+
+        let (vocab_json, merges_txt) = save_gpt2(&model).unwrap();
+        let reloaded = load_gpt2(&vocab_json, &merges_txt, &["<|endoftext|>"]).unwrap();
+        assert_eq!(model.tokenizer.lookup, reloaded.tokenizer.lookup);
+        assert_eq!(model.merges, reloaded.merges);
+
+        // special token missing from the vocabulary
+        assert!(load_gpt2(VOCAB, MERGES, &["<pad>"]).is_err());
+    }
+
+    #[test]
+    fn tokenize_agrees_with_merges_off_the_merge_path() {
+        let model = load_gpt2(VOCAB, MERGES, &["<|endoftext|>"]).unwrap();
+
+        // "hel" and "hell" only lead to "hello", " wo" to " wor"
+        for word in ["hel", "hell", " wo", "wor", "helloworl", "ld"] {
+            let mut token_buffer: Vec<usize> = vec![];
+            model
+                .tokenizer
+                .tokenize(word.as_bytes(), &mut token_buffer, &mut 0);
+            assert_eq!(token_buffer, model.encode(word.as_bytes()), "{:?}", word);
+        }
+        assert_eq!(model.encode(b"hel"), vec![8, 2]);
+    }
+
+    #[test]
+    fn gpt2_files_from_trained_bytes() {
+        // raw text has newlines, which the byte to unicode mapping moves
+        let bytes: Vec<u8> = RAW_TEXT.bytes().take(2000).collect();
+        let model = HuggingFaceBpe::from_tokenizer(generate(&bytes, 96));
+
+        let (vocab_json, merges_txt) = save_gpt2(&model).unwrap();
+        let reloaded = load_gpt2(&vocab_json, &merges_txt, &[]).unwrap();
+        assert_eq!(model.tokenizer.lookup, reloaded.tokenizer.lookup);
+        assert_eq!(model.merges, reloaded.merges);
+
+        // a special token named like a regular one would replace it
+        let mut model = model;
+        model.tokenizer.add_special_token("e");
+        assert!(save_gpt2(&model).is_err());
+    }
+}
@@ -0,0 +1,597 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    hash::Hash,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    byte_level::{bytes_to_unicode, decode_token, encode_token, pretokenize, unicode_to_bytes},
+    error::Error,
+    ranked::RankedMerges,
+    Merge, Tokenizer,
+};
+
+/// Options of the Hugging Face ByteLevel pre-tokenizer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteLevel {
+    pub add_prefix_space: bool,
+    /// split words with the GPT-2 regex before applying merges
+    pub use_regex: bool,
+}
+
+/// BPE model in the Hugging Face `tokenizers` tokenizer.json format.
+///
+/// Supports byte level models as Tokenizer<u8> and models without
+/// pre-tokenizer as Tokenizer<char>. Added tokens become special tokens,
+/// also those with `"special": false`: they are cut out of text before
+/// encoding and skipped on decode.
+/// Hugging Face encodes by merge rank, use encode / encode_text to get the
+/// same ids. Tokenizer::tokenize takes the longest token instead, falling back
+/// from trie nodes that only lead to longer tokens, its ids decode to the same
+/// input but can differ on vocabularies not trained here.
+#[derive(Debug)]
+pub struct HuggingFaceBpe<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    pub tokenizer: Tokenizer<T>,
+    /// ordered by rank
+    pub merges: Vec<Merge>,
+    pub byte_level: Option<ByteLevel>,
+    ranked: RankedMerges,
+    base: HashMap<T, usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HfFile {
+    #[serde(default)]
+    added_tokens: Vec<HfAddedToken>,
+    #[serde(default)]
+    normalizer: Option<Value>,
+    #[serde(default)]
+    pre_tokenizer: Option<Value>,
+    model: HfModel,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HfAddedToken {
+    id: usize,
+    content: String,
+    /// not used on import, every added token is made special
+    #[serde(default)]
+    special: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HfModel {
+    #[serde(rename = "type", default)]
+    model_type: Option<String>,
+    #[serde(default)]
+    continuing_subword_prefix: Option<String>,
+    #[serde(default)]
+    end_of_word_suffix: Option<String>,
+    vocab: HashMap<String, usize>,
+    #[serde(default)]
+    merges: Vec<HfMerge>,
+}
+
+/// Merges are written "left right", or as pairs by recent versions
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum HfMerge {
+    Joined(String),
+    Pair(String, String),
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Special(usize),
+}
+
+impl<T> HuggingFaceBpe<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    /// Wrap a tokenizer trained by this crate, merges are ranked by token value
+    pub fn from_tokenizer(tokenizer: Tokenizer<T>) -> Self {
+        let merges = tokenizer.merges();
+        HuggingFaceBpe::new(tokenizer, merges, None)
+    }
+
+    fn new(tokenizer: Tokenizer<T>, merges: Vec<Merge>, byte_level: Option<ByteLevel>) -> Self {
+        let base = tokenizer
+            .lookup
+            .iter()
+            .filter(|(_, token)| token.len() == 1)
+            .map(|(token_value, token)| (token[0].to_owned(), *token_value))
+            .collect();
+
+        HuggingFaceBpe {
+            ranked: RankedMerges::new(&merges),
+            tokenizer,
+            merges,
+            byte_level,
+            base,
+        }
+    }
+
+    /// Encode input as a single word, without pre-tokenization
+    pub fn encode(&self, input: &[T]) -> Vec<usize> {
+        let mut tokens: Vec<usize> = input
+            .iter()
+            .map(|elem| match self.base.get(elem) {
+                None => panic!("no child in tokenizer that matches"),
+                Some(token_value) => *token_value,
+            })
+            .collect();
+        self.ranked.apply(&mut tokens);
+        tokens
+    }
+
+    /// Cut text around special token contents, longest match first
+    fn split_specials<'a>(&self, text: &'a str) -> Vec<Segment<'a>> {
+        let mut segments = vec![];
+        let mut rest = text;
+
+        while !rest.is_empty() {
+            let found = self
+                .tokenizer
+                .special_tokens
+                .iter()
+                // an empty name would match everywhere without consuming text
+                .filter(|(name, _)| !name.is_empty())
+                .filter_map(|(name, token_value)| {
+                    rest.find(name.as_str()).map(|i| (i, name, token_value))
+                })
+                .min_by_key(|(i, name, _)| (*i, usize::MAX - name.len()));
+
+            match found {
+                None => {
+                    segments.push(Segment::Text(rest));
+                    break;
+                }
+                Some((i, name, token_value)) => {
+                    if i > 0 {
+                        segments.push(Segment::Text(&rest[..i]));
+                    }
+                    segments.push(Segment::Special(*token_value));
+                    rest = &rest[i + name.len()..];
+                }
+            }
+        }
+
+        segments
+    }
+
+    fn read(
+        json: &str,
+        parse_token: impl Fn(&str) -> Option<Vec<T>>,
+    ) -> Result<(Self, Option<Value>), Error> {
+        let file: HfFile = serde_json::from_str(json)?;
+
+        if let Some(model_type) = &file.model.model_type {
+            if model_type != "BPE" {
+                return Err(Error::Format(format!(
+                    "unsupported model type {}",
+                    model_type
+                )));
+            }
+        }
+        if file.model.continuing_subword_prefix.is_some() || file.model.end_of_word_suffix.is_some()
+        {
+            return Err(Error::Format(
+                "subword prefixes and suffixes are not supported".to_string(),
+            ));
+        }
+        if file.normalizer.is_some() {
+            return Err(Error::Format("normalizers are not supported".to_string()));
+        }
+
+        if file
+            .added_tokens
+            .iter()
+            .any(|added| added.content.is_empty())
+        {
+            return Err(Error::Format("empty added token".to_string()));
+        }
+        let special_tokens: HashMap<String, usize> = file
+            .added_tokens
+            .iter()
+            .map(|added| (added.content.to_owned(), added.id))
+            .collect();
+
+        let merges = file
+            .model
+            .merges
+            .iter()
+            .map(|merge| match merge {
+                HfMerge::Joined(joined) => joined
+                    .split_once(' ')
+                    .ok_or_else(|| Error::Format(format!("invalid merge {:?}", joined))),
+                HfMerge::Pair(left, right) => Ok((left.as_str(), right.as_str())),
+            })
+            .collect::<Result<Vec<(&str, &str)>, Error>>()?;
+
+        let model =
+            HuggingFaceBpe::from_strings(&file.model.vocab, &merges, &special_tokens, parse_token)?;
+
+        Ok((model, file.pre_tokenizer))
+    }
+
+    /// Build from a vocabulary and merges written as strings. Entries of
+    /// vocab whose value is in special_tokens become special tokens.
+    pub(crate) fn from_strings(
+        vocab: &HashMap<String, usize>,
+        merges: &[(&str, &str)],
+        special_tokens: &HashMap<String, usize>,
+        parse_token: impl Fn(&str) -> Option<Vec<T>>,
+    ) -> Result<Self, Error> {
+        let mut tokenizer = Tokenizer {
+            special_tokens: special_tokens.clone(),
+            ..Default::default()
+        };
+
+        for (token, token_value) in vocab {
+            if tokenizer.is_special_token(*token_value) {
+                continue;
+            }
+            let elements = parse_token(token)
+                .filter(|elements| !elements.is_empty())
+                .ok_or_else(|| Error::Format(format!("cannot read token {:?}", token)))?;
+            tokenizer.register(&elements, *token_value);
+        }
+
+        let find = |token: &str| {
+            vocab
+                .get(token)
+                .copied()
+                .ok_or_else(|| Error::Format(format!("merge uses unknown token {:?}", token)))
+        };
+        let merges = merges
+            .iter()
+            .map(|(left, right)| {
+                Ok(Merge {
+                    left: find(left)?,
+                    right: find(right)?,
+                    token_value: find(&[*left, *right].concat())?,
+                })
+            })
+            .collect::<Result<Vec<Merge>, Error>>()?;
+        tokenizer.check_merges(&merges)?;
+
+        Ok(HuggingFaceBpe::new(tokenizer, merges, None))
+    }
+
+    /// Vocabulary and merges written as strings, special tokens not included
+    pub(crate) fn to_strings(
+        &self,
+        render_token: impl Fn(&[T]) -> String,
+    ) -> (BTreeMap<String, usize>, Vec<(String, String)>) {
+        let vocab = self
+            .tokenizer
+            .lookup
+            .iter()
+            .map(|(token_value, token)| (render_token(token), *token_value))
+            .collect();
+
+        let merges = self
+            .merges
+            .iter()
+            .map(|merge| {
+                (
+                    render_token(&self.tokenizer.lookup[&merge.left]),
+                    render_token(&self.tokenizer.lookup[&merge.right]),
+                )
+            })
+            .collect();
+
+        (vocab, merges)
+    }
+
+    fn write(
+        &self,
+        render_token: impl Fn(&[T]) -> String,
+        pre_tokenizer: Value,
+        decoder: Value,
+    ) -> Result<String, Error> {
+        let (vocab, merges) = self.to_strings(render_token);
+
+        // the joined form is ambiguous once a token contains a space
+        let merges: Vec<HfMerge> = if merges
+            .iter()
+            .any(|(l, r)| l.contains(' ') || r.contains(' '))
+        {
+            merges
+                .into_iter()
+                .map(|(l, r)| HfMerge::Pair(l, r))
+                .collect()
+        } else {
+            merges
+                .into_iter()
+                .map(|(l, r)| HfMerge::Joined(format!("{} {}", l, r)))
+                .collect()
+        };
+
+        let mut specials: Vec<(&String, &usize)> = self.tokenizer.special_tokens.iter().collect();
+        specials.sort_unstable_by_key(|(_, token_value)| **token_value);
+        let added_tokens: Vec<Value> = specials
+            .into_iter()
+            .map(|(name, token_value)| {
+                json!({
+                    "id": token_value,
+                    "content": name,
+                    "single_word": false,
+                    "lstrip": false,
+                    "rstrip": false,
+                    "normalized": false,
+                    "special": true,
+                })
+            })
+            .collect();
+
+        let file = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": added_tokens,
+            "normalizer": null,
+            "pre_tokenizer": pre_tokenizer,
+            "post_processor": null,
+            "decoder": decoder,
+            "model": {
+                "type": "BPE",
+                "dropout": null,
+                "unk_token": null,
+                "continuing_subword_prefix": null,
+                "end_of_word_suffix": null,
+                "fuse_unk": false,
+                "byte_fallback": false,
+                "vocab": vocab,
+                "merges": merges,
+            },
+        });
+
+        Ok(serde_json::to_string_pretty(&file)?)
+    }
+}
+
+impl HuggingFaceBpe<u8> {
+    /// Load a byte level BPE tokenizer.json
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let mapping = unicode_to_bytes();
+        let (mut model, pre_tokenizer) =
+            HuggingFaceBpe::read(json, |token| decode_token(token, &mapping))?;
+
+        model.byte_level = match pre_tokenizer {
+            Some(pre_tokenizer) if pre_tokenizer["type"] == "ByteLevel" => Some(ByteLevel {
+                add_prefix_space: pre_tokenizer["add_prefix_space"].as_bool().unwrap_or(true),
+                use_regex: pre_tokenizer["use_regex"].as_bool().unwrap_or(true),
+            }),
+            _ => {
+                return Err(Error::Format(
+                    "expected a ByteLevel pre-tokenizer, load as Tokenizer<char> instead"
+                        .to_string(),
+                ))
+            }
+        };
+
+        Ok(model)
+    }
+
+    /// Write a byte level tokenizer.json. Without options the whole input is
+    /// a single word, as with Tokenizer::tokenize.
+    pub fn to_json(&self) -> Result<String, Error> {
+        let byte_level = self.byte_level.unwrap_or(ByteLevel {
+            add_prefix_space: false,
+            use_regex: false,
+        });
+        let table = bytes_to_unicode();
+
+        self.write(
+            |token| encode_token(token, &table),
+            json!({
+                "type": "ByteLevel",
+                "add_prefix_space": byte_level.add_prefix_space,
+                "trim_offsets": true,
+                "use_regex": byte_level.use_regex,
+            }),
+            json!({
+                "type": "ByteLevel",
+                "add_prefix_space": true,
+                "trim_offsets": true,
+                "use_regex": true,
+            }),
+        )
+    }
+
+    /// Encode text with special tokens and the byte level pre-tokenizer
+    pub fn encode_text(&self, text: &str) -> Vec<usize> {
+        let byte_level = self.byte_level.unwrap_or(ByteLevel {
+            add_prefix_space: false,
+            use_regex: false,
+        });
+
+        let mut tokens = vec![];
+        for segment in self.split_specials(text) {
+            match segment {
+                Segment::Special(token_value) => tokens.push(token_value),
+                Segment::Text(text) => {
+                    let text = if byte_level.add_prefix_space && !text.starts_with(' ') {
+                        format!(" {}", text)
+                    } else {
+                        text.to_owned()
+                    };
+
+                    let words = if byte_level.use_regex {
+                        pretokenize(&text)
+                    } else {
+                        vec![text.as_str()]
+                    };
+                    for word in words {
+                        tokens.extend(self.encode(word.as_bytes()));
+                    }
+                }
+            }
+        }
+
+        tokens
+    }
+}
+
+impl HuggingFaceBpe<char> {
+    /// Load a BPE tokenizer.json that has no pre-tokenizer, every character
+    /// of the vocabulary is an element
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let (model, pre_tokenizer) =
+            HuggingFaceBpe::read(json, |token| Some(token.chars().collect()))?;
+
+        if pre_tokenizer.is_some() {
+            return Err(Error::Format(
+                "pre-tokenizers are not supported for Tokenizer<char>".to_string(),
+            ));
+        }
+
+        Ok(model)
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        self.write(
+            |token| token.iter().collect(),
+            Value::Null,
+            json!({ "type": "Fuse" }),
+        )
+    }
+
+    /// Encode text with special tokens, the rest as a single word
+    pub fn encode_text(&self, text: &str) -> Vec<usize> {
+        let mut tokens = vec![];
+        for segment in self.split_specials(text) {
+            match segment {
+                Segment::Special(token_value) => tokens.push(token_value),
+                Segment::Text(text) => {
+                    let chars: Vec<char> = text.chars().collect();
+                    tokens.extend(self.encode(&chars));
+                }
+            }
+        }
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HuggingFaceBpe;
+    use crate::{
+        binary::{load_binary, save_binary},
+        bytes::ByteTokenizer,
+        generate,
+        mapped::{write_mapped, MappedTokenizer},
+        stream::StreamingEncoder,
+        test_data::RAW_TEXT,
+        Tokenizer,
+    };
+
+    const FIXTURE: &str = include_str!("../fixtures/hf_byte_level.json");
+
+    #[test]
+    fn hugging_face_fixture() {
+        let model = HuggingFaceBpe::<u8>::from_json(FIXTURE).unwrap();
+        assert_eq!(model.tokenizer.special_token("<|endoftext|>"), Some(16));
+
+        // ids worked out by hand with the Hugging Face BPE algorithm,
+        // "world" uses "or" since its merge ranks before "w o"
+        let text = "hello world<|endoftext|>world";
+        let expected = vec![11, 14, 15, 16, 5, 13, 15];
+        assert_eq!(model.encode_text(text), expected);
+
+        // not prefix closed, checked loading still accepts it
+        let mut saved = vec![];
+        save_binary(&model.tokenizer, &mut saved).unwrap();
+        let loaded: Tokenizer<u8> = load_binary(&mut &saved[..]).unwrap();
+        assert_eq!(model.tokenizer.lookup, loaded.lookup);
+        assert_eq!(model.tokenizer.special_tokens, loaded.special_tokens);
+
+        // export and load again
+        let exported = model.to_json().unwrap();
+        let reloaded = HuggingFaceBpe::<u8>::from_json(&exported).unwrap();
+        assert_eq!(model.tokenizer.lookup, reloaded.tokenizer.lookup);
+        assert_eq!(model.merges, reloaded.merges);
+        assert_eq!(reloaded.encode_text(text), expected);
+    }
+
+    #[test]
+    fn tokenize_through_prefixes_that_are_not_tokens() {
+        let model = HuggingFaceBpe::<u8>::from_json(FIXTURE).unwrap();
+        let byte_tokenizer = ByteTokenizer::from(&model.tokenizer);
+        let compiled = model.tokenizer.compile();
+        let mut mapped_bytes = vec![];
+        write_mapped(&byte_tokenizer, &mut mapped_bytes).unwrap();
+        let mapped = MappedTokenizer::from_bytes(mapped_bytes).unwrap();
+
+        let mut token_buffer: Vec<usize> = vec![];
+        model.tokenizer.tokenize(b"hel", &mut token_buffer, &mut 0);
+        assert_eq!(token_buffer, vec![8, 2]);
+
+        // "hel" and "hell" only lead to "hello", " wo" to " wor"
+        for text in ["hel", "hell", "helo", " wo", "hellow", "hel wo"] {
+            let input = text.as_bytes();
+            let expected = model.encode(input);
+
+            let mut token_buffer: Vec<usize> = vec![];
+            model.tokenizer.tokenize(input, &mut token_buffer, &mut 0);
+            assert_eq!(token_buffer, expected, "{:?}", text);
+
+            let lazy: Vec<usize> = model.tokenizer.iter_tokens(input).collect();
+            assert_eq!(lazy, expected, "{:?}", text);
+
+            token_buffer.clear();
+            byte_tokenizer.tokenize(input, &mut token_buffer, &mut 0);
+            assert_eq!(token_buffer, expected, "{:?}", text);
+
+            token_buffer.clear();
+            compiled.tokenize(input, &mut token_buffer, &mut 0);
+            assert_eq!(token_buffer, expected, "{:?}", text);
+
+            token_buffer.clear();
+            mapped.tokenize(input, &mut token_buffer, &mut 0);
+            assert_eq!(token_buffer, expected, "{:?}", text);
+
+            token_buffer.clear();
+            let mut encoder = StreamingEncoder::new(&model.tokenizer);
+            for chunk in input.chunks(1) {
+                encoder.push(chunk, &mut token_buffer);
+            }
+            encoder.finish(&mut token_buffer);
+            assert_eq!(token_buffer, expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn hugging_face_export_char_tokenizer() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 96);
+        tokenizer.add_special_token("<eos>");
+
+        let model = HuggingFaceBpe::from_tokenizer(tokenizer);
+        let exported = model.to_json().unwrap();
+        let reloaded = HuggingFaceBpe::<char>::from_json(&exported).unwrap();
+
+        assert_eq!(model.tokenizer.lookup, reloaded.tokenizer.lookup);
+        assert_eq!(
+            model.tokenizer.special_tokens,
+            reloaded.tokenizer.special_tokens
+        );
+
+        let text: String = text_val.iter().collect();
+        assert_eq!(model.encode(&text_val), reloaded.encode_text(&text));
+
+        // empty special names match nothing and are refused on import
+        let mut model = model;
+        model.tokenizer.add_special_token("");
+        assert_eq!(model.encode_text(&text), reloaded.encode_text(&text));
+        assert!(HuggingFaceBpe::<char>::from_json(&model.to_json().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use std::{fmt::Debug, hash::Hash, iter::FusedIterator, ops::Range};
+
+use crate::Tokenizer;
+
+/// Iterator over the tokens of an input, walking the trie only when next is called
+pub struct TokenSpanIter<'a, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    tokenizer: &'a Tokenizer<T>,
+    input: &'a [T],
+    pointer: usize,
+}
+
+impl<'a, T> TokenSpanIter<'a, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    pub fn new(tokenizer: &'a Tokenizer<T>, input: &'a [T]) -> Self {
+        TokenSpanIter {
+            tokenizer,
+            input,
+            pointer: 0,
+        }
+    }
+
+    /// Part of the input that has not been tokenized yet
+    pub fn remaining(&self) -> &'a [T] {
+        &self.input[self.pointer..]
+    }
+}
+
+impl<T> Iterator for TokenSpanIter<'_, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    type Item = (usize, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pointer >= self.input.len() {
+            return None;
+        }
+
+        let start = self.pointer;
+        let node = self.tokenizer.find_longest(self.input, &mut self.pointer);
+
+        Some((node.token_value, start..self.pointer))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.input.len() - self.pointer;
+        (remaining.min(1), Some(remaining))
+    }
+}
+
+impl<T> FusedIterator for TokenSpanIter<'_, T> where T: Eq + Hash + Clone + Debug {}
+
+/// Same as TokenSpanIter, without the spans
+pub struct TokenIter<'a, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    inner: TokenSpanIter<'a, T>,
+}
+
+impl<'a, T> TokenIter<'a, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    pub fn new(tokenizer: &'a Tokenizer<T>, input: &'a [T]) -> Self {
+        TokenIter {
+            inner: TokenSpanIter::new(tokenizer, input),
+        }
+    }
+
+    /// Part of the input that has not been tokenized yet
+    pub fn remaining(&self) -> &'a [T] {
+        self.inner.remaining()
+    }
+}
+
+impl<T> Iterator for TokenIter<'_, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(token, _)| token)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<T> FusedIterator for TokenIter<'_, T> where T: Eq + Hash + Clone + Debug {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{generate, test_data::RAW_TEXT};
+
+    #[test]
+    fn iter_matches_buffered_tokenize() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let tokenizer = generate(&text_val, 128);
+
+        let mut token_buffer: Vec<usize> = vec![];
+        tokenizer.tokenize(&text_val, &mut token_buffer, &mut 0);
+
+        let lazy: Vec<usize> = tokenizer.iter_tokens(&text_val).collect();
+        assert_eq!(token_buffer, lazy);
+
+        // spans are contiguous and decode to the covered input
+        let mut expected_start = 0;
+        for (token, span) in tokenizer.iter_tokens_with_span(&text_val) {
+            assert_eq!(span.start, expected_start);
+            assert_eq!(tokenizer.lookup[&token], text_val[span.clone()]);
+            expected_start = span.end;
+        }
+        assert_eq!(expected_start, text_val.len());
+    }
+
+    #[test]
+    fn iter_stops_early() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let tokenizer = generate(&text_val, 64);
+
+        let mut iter = tokenizer.iter_tokens(&text_val);
+        let first: Vec<usize> = iter.by_ref().take(3).collect();
+        assert_eq!(first.len(), 3);
+
+        let consumed = text_val.len() - iter.remaining().len();
+        let decoded: usize = first.iter().map(|t| tokenizer.lookup[t].len()).sum();
+        assert_eq!(consumed, decoded);
+    }
+}
@@ -1,14 +1,47 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     fmt::Debug,
-    hash::Hash, time::Instant,
+    hash::Hash,
 };
 
 use serde::{Deserialize, Serialize};
 
+use compiled::CompiledTokenizer;
+use error::Error;
+use iter::{TokenIter, TokenSpanIter};
+
+pub mod binary;
+pub mod byte_level;
+pub mod bytes;
+pub mod compiled;
+pub mod dataset;
+pub mod dump;
+mod entries;
+pub mod error;
+pub mod files;
+pub mod gpt2;
+pub mod huggingface;
+pub mod iter;
+pub mod mapped;
+pub mod midi;
+pub mod npy;
+#[cfg(feature = "python")]
+mod python;
+pub mod quantize;
+pub mod ranked;
+pub mod sentencepiece;
+pub mod stream;
+pub mod tiktoken;
+pub mod tracks;
+pub mod validation;
+pub mod vgm;
+pub mod vocabulary;
 pub mod with_rayon;
 
 #[cfg(test)]
+mod temp_path;
+#[cfg(test)]
+#[allow(clippy::redundant_static_lifetimes)]
 pub mod test_data;
 
 
@@ -21,6 +54,7 @@ where
     /// we are guaranteed that subpairs exist, this node definitely has a value
     /// so no need for options
     pub token_value: usize,
+    #[serde(with = "entries::children")]
     pub children: HashMap<T, Node<T>>,
 }
 
@@ -29,95 +63,144 @@ where
     T: Eq + Hash + Clone + Debug,
 {
     pub fn new(byte_value: &[T], token_value: usize) -> Node<T> {
-        if byte_value.len() == 1 {
-            return Node {
-                byte_value: byte_value[0].to_owned(),
-                token_value: token_value,
-                children: HashMap::new(),
-            };
-        } else {
+        // build from the leaf up, only the leaf holds the token value
+        let (last, rest) = byte_value.split_last().expect("cannot create node from empty value");
+        let mut node = Node {
+            byte_value: last.to_owned(),
+            token_value,
+            children: HashMap::new(),
+        };
+
+        for elem in rest.iter().rev() {
             let mut children = HashMap::new();
-            children.insert(
-                byte_value[1].to_owned(),
-                Node::new(&byte_value[1..], token_value),
-            );
+            children.insert(node.byte_value.to_owned(), node);
 
-            return Node {
-                byte_value: byte_value[0].to_owned(),
+            node = Node {
+                byte_value: elem.to_owned(),
                 token_value: 0,
-                children: children,
+                children,
             };
         }
+
+        node
     }
 
     pub fn register(&mut self, byte_value: &[T], token_value: usize) {
-        if byte_value.len() == 1 {
-            // unordered tokens, update value here
-            self.token_value = token_value;
-        } else {
-            match self.children.get_mut(&byte_value[1]) {
-                None => {
-                    let child_node = Node::new(&byte_value[1..], token_value);
-                    self.children.insert(byte_value[1].to_owned(), child_node);
-                }
-                Some(child) => {
-                    child.register(&byte_value[1..], token_value);
-                }
+        let mut node = self;
+
+        for (depth, elem) in byte_value.iter().enumerate().skip(1) {
+            if !node.children.contains_key(elem) {
+                let child_node = Node::new(&byte_value[depth..], token_value);
+                node.children.insert(elem.to_owned(), child_node);
+                return;
             }
+            node = node.children.get_mut(elem).unwrap();
         }
+
+        // unordered tokens, update value here
+        node.token_value = token_value;
     }
 
     pub fn tokenize(
         &self,
-        read_buffer: &Vec<T>,
+        read_buffer: &[T],
         write_buffer: &mut Vec<usize>,
         pointer: &mut usize,
     ) {
-        *pointer += 1;
+        write_buffer.push(self.find_longest(read_buffer, pointer).token_value);
+    }
 
-        // check if not oob
-        if *pointer < read_buffer.len() {
-            match self.children.get(&read_buffer[*pointer]) {
-                Some(child) => child.tokenize(read_buffer, write_buffer, pointer),
-                None => write_buffer.push(self.token_value),
-            }
-        } else {
-            write_buffer.push(self.token_value);
+    /// Follow children as far as read_buffer allows, return the deepest
+    /// matched node that ends a token and leave pointer right after it.
+    /// Without the lookup of the tokenizer, self and nodes holding a value
+    /// other than 0 are taken as tokens, Tokenizer::find_longest also knows
+    /// where the token with value 0 ends
+    pub fn find_longest(&self, read_buffer: &[T], pointer: &mut usize) -> &Node<T> {
+        let ends_token = |node: &Node<T>| std::ptr::eq(node, self) || node.token_value != 0;
+        match self.find_longest_by(read_buffer, pointer, ends_token) {
+            None => unreachable!("the first node always ends a token"),
+            Some(node) => node,
         }
     }
 
-    fn tokenize_no_write(&self, read_buffer: &[T], pointer: &mut usize) {
-        *pointer += 1;
-
-        if *pointer < read_buffer.len() {
-            match self.children.get(&read_buffer[*pointer]) {
-                None => (),
-                Some(child) => child.tokenize_no_write(read_buffer, pointer),
+    /// Walk as far as read_buffer allows, then fall back to the deepest node
+    /// ends_token accepts. Vocabularies that are not prefix-closed have nodes
+    /// that only lead to longer tokens, stopping on one would emit a wrong
+    /// value. Pointer is left untouched if no node is accepted.
+    fn find_longest_by(
+        &self,
+        read_buffer: &[T],
+        pointer: &mut usize,
+        ends_token: impl Fn(&Node<T>) -> bool,
+    ) -> Option<&Node<T>> {
+        let mut node = self;
+        let mut end = *pointer + 1;
+        let mut longest = None;
+
+        loop {
+            if ends_token(node) {
+                longest = Some((node, end));
+            }
+            match read_buffer.get(end).and_then(|elem| node.children.get(elem)) {
+                None => break,
+                Some(child) => {
+                    node = child;
+                    end += 1;
+                }
             }
         }
+
+        let (node, end) = longest?;
+        *pointer = end;
+        Some(node)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: Deserialize<'de>"))]
 pub struct Tokenizer<T>
 where
     T: Eq + Hash + Clone + Debug,
 {
+    #[serde(with = "entries::children")]
     pub children: HashMap<T, Node<T>>,
+    #[serde(with = "entries::lookup")]
     pub lookup: HashMap<usize, Vec<T>>,
+    /// named tokens that have no elements (separators, end of sequence...),
+    /// they are never produced by tokenize and are pushed by the caller
+    #[serde(default)]
+    pub special_tokens: HashMap<String, usize>,
+    /// occurrences of each token in the training input when it was added
+    #[serde(default)]
+    pub training_counts: HashMap<usize, usize>,
 }
 
-impl<T> Tokenizer<T>
+/// Token built by joining two existing tokens
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Merge {
+    pub left: usize,
+    pub right: usize,
+    pub token_value: usize,
+}
+
+impl<T> Default for Tokenizer<T>
 where
     T: Eq + Hash + Clone + Debug,
 {
-    pub fn default() -> Self {
-        return Tokenizer {
+    fn default() -> Self {
+        Tokenizer {
             children: HashMap::new(),
             lookup: HashMap::new(),
-        };
+            special_tokens: HashMap::new(),
+            training_counts: HashMap::new(),
+        }
     }
+}
 
+impl<T> Tokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
     pub fn register(&mut self, token: &[T], token_value: usize) {
         self.lookup.insert(token_value, token.to_vec().to_owned());
 
@@ -133,40 +216,190 @@ where
         }
     }
 
+    /// Add a named special token after every existing token value, or
+    /// return the value it already has
+    pub fn add_special_token(&mut self, name: &str) -> usize {
+        if let Some(token_value) = self.special_tokens.get(name) {
+            return *token_value;
+        }
+
+        let token_value = self
+            .lookup
+            .keys()
+            .chain(self.special_tokens.values())
+            .max()
+            .map_or(0, |max| max + 1);
+        self.special_tokens.insert(name.to_owned(), token_value);
+
+        token_value
+    }
+
+    pub fn special_token(&self, name: &str) -> Option<usize> {
+        self.special_tokens.get(name).copied()
+    }
+
+    pub fn is_special_token(&self, token_value: usize) -> bool {
+        self.special_tokens.values().any(|value| *value == token_value)
+    }
+
+    /// Number of token values in use, special tokens included
+    pub fn vocabulary_size(&self) -> usize {
+        self.lookup.len() + self.special_tokens.len()
+    }
+
+    /// Token value of every sequence in lookup
+    pub fn reverse_lookup(&self) -> HashMap<&[T], usize> {
+        self.lookup
+            .iter()
+            .map(|(token_value, token)| (&token[..], *token_value))
+            .collect()
+    }
+
+    /// Pair of existing tokens each multi-element token can be built from,
+    /// ordered by token value. Training only ever extends a token by one
+    /// element, so the longest prefix is tried first. Tokens that cannot be
+    /// split into two known tokens have no merge.
+    pub fn merges(&self) -> Vec<Merge> {
+        let reverse = self.reverse_lookup();
+
+        let mut token_values: Vec<usize> = self.lookup.keys().copied().collect();
+        token_values.sort_unstable();
+
+        token_values
+            .into_iter()
+            .filter_map(|token_value| {
+                let token = &self.lookup[&token_value];
+                (1..token.len()).rev().find_map(|split| {
+                    let left = reverse.get(&token[..split])?;
+                    let right = reverse.get(&token[split..])?;
+                    Some(Merge {
+                        left: *left,
+                        right: *right,
+                        token_value,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Error if a merge does not join two known tokens into the token it names
+    pub fn check_merges(&self, merges: &[Merge]) -> Result<(), Error> {
+        for merge in merges {
+            let joined = match (self.lookup.get(&merge.left), self.lookup.get(&merge.right)) {
+                (Some(left), Some(right)) => [&left[..], &right[..]].concat(),
+                _ => {
+                    return Err(Error::Format(format!(
+                        "merge into {} uses unknown tokens",
+                        merge.token_value
+                    )))
+                }
+            };
+
+            if self.lookup.get(&merge.token_value) != Some(&joined) {
+                return Err(Error::Format(format!(
+                    "merge {} + {} does not match token {}",
+                    merge.left, merge.right, merge.token_value
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn tokenize(
         &self,
-        read_buffer: &Vec<T>,
+        read_buffer: &[T],
         write_buffer: &mut Vec<usize>,
         pointer: &mut usize,
     ) {
         while *pointer < read_buffer.len() {
-            match self.children.get(&read_buffer[*pointer]) {
-                None => panic!("no child in tokenizer that matches"),
-                Some(child) => child.tokenize(read_buffer, write_buffer, pointer),
-            }
+            write_buffer.push(self.find_longest(read_buffer, pointer).token_value);
         }
     }
 
-    /// From buffer find token and move pointer for single element
-    fn tokenize_item_no_write(&self, buffer: &[T], pointer: &mut usize) {
-        match self.children.get(&buffer[*pointer]) {
-            None => panic!("child not found"),
-            Some(child) => {
-                child.tokenize_no_write(buffer, pointer);
-            }
+    /// From buffer find the longest token starting at pointer and move pointer past it
+    pub fn find_longest(&self, buffer: &[T], pointer: &mut usize) -> &Node<T> {
+        let child = match self.children.get(&buffer[*pointer]) {
+            None => panic!("no child in tokenizer that matches"),
+            Some(child) => child,
+        };
+        match child.find_longest_by(buffer, pointer, |node| self.ends_token(node)) {
+            None => panic!("no token starts with {:?}", buffer[*pointer]),
+            Some(node) => node,
         }
     }
 
-    pub fn detokenize(&self, read_buffer: &Vec<usize>, write_buffer: &mut Vec<T>) {
+    /// True if the path to node spells a token. Nodes that only lead to
+    /// longer tokens hold 0, which is also the value of a real token
+    pub(crate) fn ends_token(&self, node: &Node<T>) -> bool {
+        node.token_value != 0
+            || self
+                .lookup
+                .get(&0)
+                .and_then(|token| self.find_node(token))
+                .is_some_and(|zero| std::ptr::eq(zero, node))
+    }
+
+    /// Freeze the trie into a flat read-only structure, faster for encoding
+    pub fn compile(&self) -> CompiledTokenizer<T> {
+        CompiledTokenizer::new(self)
+    }
+
+    /// Lazily tokenize input, one token per call to next
+    pub fn iter_tokens<'a>(&'a self, input: &'a [T]) -> TokenIter<'a, T> {
+        TokenIter::new(self, input)
+    }
+
+    /// Lazily tokenize input, yielding each token with the range of input it covers
+    pub fn iter_tokens_with_span<'a>(&'a self, input: &'a [T]) -> TokenSpanIter<'a, T> {
+        TokenSpanIter::new(self, input)
+    }
+
+    /// Special tokens have no elements and are skipped
+    pub fn detokenize(&self, read_buffer: &[usize], write_buffer: &mut Vec<T>) {
         for elem in read_buffer {
-            write_buffer.extend_from_slice(self.lookup.get(elem).unwrap());
+            match self.lookup.get(elem) {
+                Some(token) => write_buffer.extend_from_slice(token),
+                None if self.is_special_token(*elem) => (),
+                None => panic!("unknown token value {}", elem),
+            }
         }
     }
 }
 
-pub fn generate<T>(input: &Vec<T>, target_vocabulary_size: usize) -> Tokenizer<T>
+pub fn generate<T>(input: &[T], target_vocabulary_size: usize) -> Tokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    generate_with_max_length(input, target_vocabulary_size, usize::MAX)
+}
+
+/// Same as generate, merges never produce tokens longer than max_token_length.
+/// Stops before target_vocabulary_size when no pair is left to merge
+pub fn generate_with_max_length<T>(
+    input: &[T],
+    target_vocabulary_size: usize,
+    max_token_length: usize,
+) -> Tokenizer<T>
 where
     T: Eq + Hash + Clone + Debug,
+{
+    generate_with_key(input, target_vocabulary_size, max_token_length, |_| ())
+}
+
+/// Same as generate_with_max_length, merges only join elements with the same
+/// key, e.g. the channel of a register write, so every token stays within one
+/// stream. A key that changes at boundaries works as a segmentation
+pub fn generate_with_key<T, K, F>(
+    input: &[T],
+    target_vocabulary_size: usize,
+    max_token_length: usize,
+    key: F,
+) -> Tokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug,
+    K: Eq,
+    F: Fn(&T) -> K,
 {
     let mut tokenizer = Tokenizer::default();
 
@@ -177,20 +410,19 @@ where
 
     // perform dedup on base input
     {
-        let mut token_set = HashSet::new();
-        for elem in &input[..] {
-            token_set.insert(elem);
+        let mut token_set: HashMap<&T, usize> = HashMap::new();
+        for elem in input {
+            *token_set.entry(elem).or_insert(0) += 1;
         }
 
-        for elem in token_set {
+        for (elem, count) in token_set {
             tokenizer.register(&[elem.to_owned()], curr_token_value);
+            tokenizer.training_counts.insert(curr_token_value, count);
             straight_lookup.insert(vec![elem.clone()], curr_token_value);
             curr_token_value += 1;
         }
     }
 
-
-    let now = Instant::now();
     // now create pairs
     while curr_token_value < target_vocabulary_size {
         // create pairs by using the tokenizer to tokenize input values
@@ -200,11 +432,13 @@ where
         while pointer < input.len() {
             let curr_val_pointer = pointer;
 
-            tokenizer.tokenize_item_no_write(input, &mut pointer);
-            //pointer += 1;
+            tokenizer.find_longest(input, &mut pointer);
 
-            if pointer < input.len() - 1 {
-                //println!("pointer in larger: {}", pointer);
+            if pointer + 1 < input.len() {
+                // the next token starts at a different key
+                if key(&input[pointer - 1]) != key(&input[pointer]) {
+                    continue;
+                }
                 pointer += 1;
                 pairs_count
                     .entry(&input[curr_val_pointer..pointer])
@@ -219,30 +453,35 @@ where
         }
 
         // find biggest that is not in tokenizer
-        let (max_key, _) = pairs_count
+        let (max_key, max_count) = match pairs_count
             .into_iter()
-            .filter(|(key, _)| straight_lookup.get(&key.to_owned().to_vec()).is_none())
+            .filter(|(key, _)| key.len() <= max_token_length && !straight_lookup.contains_key(*key))
             .max_by_key(|(_, pair_count)| *pair_count)
-            .unwrap();
+        {
+            Some(max) => max,
+            // nothing left to merge
+            None => break,
+        };
 
         // add biggest to tokenizer
         tokenizer.register(max_key, curr_token_value);
+        tokenizer.training_counts.insert(curr_token_value, max_count);
+        straight_lookup.insert(max_key.to_vec(), curr_token_value);
 
         // increment id tracker
         curr_token_value += 1;
     }
-    let elapsed = now.elapsed();
-    println!("Elapsed: {:.2?}", elapsed);
 
-
-    return tokenizer;
+    tokenizer
 }
 
 #[cfg(test)]
 mod tests {
+    #[allow(unused_imports)]
     use std::{fs::File, io::Write};
+    use std::collections::HashSet;
 
-    use super::generate;
+    use super::{generate, Node, Tokenizer};
 
     use super::test_data::RAW_TEXT;
 
@@ -280,4 +519,108 @@ mod tests {
         }
         */
     }
+
+    #[test]
+    fn stop_when_nothing_left_to_merge() {
+        let text_val: Vec<char> = "abababab".chars().collect();
+
+        let tokenizer = generate(&text_val, 64);
+        assert!(tokenizer.lookup.len() < 64);
+        assert!(tokenizer.validate().is_valid());
+
+        let tokenizer = super::generate_with_max_length(&text_val, 64, 2);
+        assert!(tokenizer.lookup.values().all(|token| token.len() <= 2));
+
+        let mut token_buffer: Vec<usize> = vec![];
+        tokenizer.tokenize(&text_val, &mut token_buffer, &mut 0);
+        assert_eq!(token_buffer.len(), 4);
+    }
+
+    #[test]
+    fn merged_pairs_are_registered_once() {
+        let text_val: Vec<char> = "aaaaaaaaaaaaaaaab".chars().collect();
+
+        let tokenizer = generate(&text_val, 64);
+        let tokens: HashSet<&Vec<char>> = tokenizer.lookup.values().collect();
+        assert_eq!(tokens.len(), tokenizer.lookup.len());
+        assert!(tokenizer.validate().is_valid());
+    }
+
+    #[test]
+    fn train_on_inputs_without_pairs() {
+        let tokenizer = generate(&['a'], 8);
+        assert_eq!(tokenizer.lookup.len(), 1);
+
+        // the last element of an input has no pair
+        let tokenizer = super::with_rayon::parallel_generate_with_base_vocabulary(
+            vec![vec![], vec!['a'], vec!['b']],
+            vec!['a', 'b'],
+            8,
+        );
+        assert_eq!(tokenizer.lookup.len(), 2);
+    }
+
+    #[test]
+    fn merge_within_key_only() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        struct Register {
+            channel: u8,
+            register: u8,
+            value: u8,
+        }
+
+        // two channels taking turns writing the same pattern
+        let input: Vec<Register> = (0..240u8)
+            .map(|i| Register {
+                channel: (i / 8) % 2,
+                register: i % 3,
+                value: i % 4,
+            })
+            .collect();
+
+        let tokenizer = super::generate_with_key(&input, 64, usize::MAX, |elem| elem.channel);
+        assert!(tokenizer.lookup.len() > 30);
+        for token in tokenizer.lookup.values() {
+            assert!(token.iter().all(|elem| elem.channel == token[0].channel));
+        }
+
+        let mut token_buffer: Vec<usize> = vec![];
+        tokenizer.tokenize(&input, &mut token_buffer, &mut 0);
+        let mut detokenized = vec![];
+        tokenizer.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(detokenized, input);
+    }
+
+    #[test]
+    fn register_very_long_token() {
+        let long_token: Vec<u16> = (0..100_000).map(|i| (i % 251) as u16).collect();
+
+        let mut tokenizer = Tokenizer::default();
+        for elem in 0..251 {
+            tokenizer.register(&[elem], elem as usize);
+        }
+        tokenizer.register(&long_token, 251);
+        tokenizer.register(&long_token[..50_000], 252);
+
+        let mut token_buffer: Vec<usize> = vec![];
+        tokenizer.tokenize(&long_token, &mut token_buffer, &mut 0);
+        assert_eq!(token_buffer, vec![251]);
+
+        // prefix followed by an element that leaves the long token's path
+        let mut half = long_token[..50_000].to_vec();
+        half.push(250);
+        token_buffer.clear();
+        tokenizer.tokenize(&half, &mut token_buffer, &mut 0);
+        assert_eq!(token_buffer, vec![252, 250]);
+
+        let mut detokenized = vec![];
+        tokenizer.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(detokenized, half);
+
+        // dropping the trie recurses once per element, take it apart first
+        let mut stack: Vec<Node<u16>> = tokenizer.children.drain().map(|(_, node)| node).collect();
+        while let Some(mut node) = stack.pop() {
+            stack.extend(node.children.drain().map(|(_, child)| child));
+        }
+    }
 }
@@ -0,0 +1,279 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    bytes::{ByteTokenizer, NO_NODE, NO_TOKEN},
+    error::Error,
+};
+
+const MAGIC: &[u8; 4] = b"BPEM";
+/// version 2 marks nodes that are not tokens with NO_TOKEN, version 1 files
+/// stored them as 0 and are refused
+const VERSION: u32 = 2;
+/// magic, version, node count, edge count, token count, lookup bytes length
+const HEADER_LEN: usize = 24;
+
+/// Write a ByteTokenizer in the layout read by MappedTokenizer.
+///
+/// All integers are little endian u32. After the header come the root table
+/// (256 entries), node tokens, child offsets, child targets and lookup offsets,
+/// then the byte arrays child labels and lookup bytes.
+pub fn write_mapped<W: Write>(byte_tokenizer: &ByteTokenizer, writer: &mut W) -> io::Result<()> {
+    // an empty tokenizer still has the leading offset
+    let lookup_offsets: &[u32] = if byte_tokenizer.lookup_offsets.is_empty() {
+        &[0]
+    } else {
+        &byte_tokenizer.lookup_offsets
+    };
+
+    writer.write_all(MAGIC)?;
+    for value in [
+        VERSION,
+        byte_tokenizer.node_tokens.len() as u32,
+        byte_tokenizer.child_labels.len() as u32,
+        (lookup_offsets.len() - 1) as u32,
+        byte_tokenizer.lookup_bytes.len() as u32,
+    ] {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+
+    for section in [
+        &byte_tokenizer.root[..],
+        &byte_tokenizer.node_tokens,
+        &byte_tokenizer.child_offsets,
+        &byte_tokenizer.child_targets,
+        lookup_offsets,
+    ] {
+        for value in section {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+
+    writer.write_all(&byte_tokenizer.child_labels)?;
+    writer.write_all(&byte_tokenizer.lookup_bytes)?;
+
+    Ok(())
+}
+
+/// Byte tokenizer read in place from the layout written by write_mapped.
+/// Only Tokenizer<u8> is supported, through ByteTokenizer, tokenizers over
+/// other element types have no mapped layout. Nothing is deserialized, values are decoded from the buffer when the trie
+/// is walked, so processes mapping the same file share a single copy.
+///
+/// Only section sizes are checked when opening, a corrupted file can make
+/// encoding panic.
+pub struct MappedTokenizer<B>
+where
+    B: AsRef<[u8]>,
+{
+    data: B,
+    node_count: usize,
+    token_count: usize,
+    node_tokens: usize,
+    child_offsets: usize,
+    child_targets: usize,
+    lookup_offsets: usize,
+    child_labels: usize,
+    lookup_bytes: usize,
+}
+
+impl MappedTokenizer<Mmap> {
+    /// Memory map a file written by write_mapped
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        // SAFETY: the map is read only, the file must not be truncated while in use
+        let map = unsafe { Mmap::map(&file)? };
+        MappedTokenizer::from_bytes(map)
+    }
+}
+
+impl<B> MappedTokenizer<B>
+where
+    B: AsRef<[u8]>,
+{
+    pub fn from_bytes(data: B) -> Result<Self, Error> {
+        let bytes = data.as_ref();
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(Error::Format("not a mapped tokenizer file".to_string()));
+        }
+
+        let version = read_u32(bytes, 4, 0);
+        if version != VERSION {
+            return Err(Error::Format(format!("unsupported version {}", version)));
+        }
+
+        let node_count = read_u32(bytes, 8, 0) as usize;
+        let edge_count = read_u32(bytes, 12, 0) as usize;
+        let token_count = read_u32(bytes, 16, 0) as usize;
+        let lookup_len = read_u32(bytes, 20, 0) as usize;
+
+        let root = HEADER_LEN;
+        let node_tokens = root + 256 * 4;
+        let child_offsets = node_tokens + node_count * 4;
+        let child_targets = child_offsets + (node_count + 1) * 4;
+        let lookup_offsets = child_targets + edge_count * 4;
+        let child_labels = lookup_offsets + (token_count + 1) * 4;
+        let lookup_bytes = child_labels + edge_count;
+        let end = lookup_bytes + lookup_len;
+
+        if bytes.len() != end {
+            return Err(Error::Format(format!(
+                "expected {} bytes, found {}",
+                end,
+                bytes.len()
+            )));
+        }
+
+        Ok(MappedTokenizer {
+            data,
+            node_count,
+            token_count,
+            node_tokens,
+            child_offsets,
+            child_targets,
+            lookup_offsets,
+            child_labels,
+            lookup_bytes,
+        })
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    /// Bytes of a token, None if the tokenizer has no such token
+    pub fn token_bytes(&self, token_value: usize) -> Option<&[u8]> {
+        if token_value >= self.token_count {
+            return None;
+        }
+
+        let bytes = self.data.as_ref();
+        let start = read_u32(bytes, self.lookup_offsets, token_value) as usize;
+        let end = read_u32(bytes, self.lookup_offsets, token_value + 1) as usize;
+
+        if start == end {
+            return None;
+        }
+        Some(&bytes[self.lookup_bytes + start..self.lookup_bytes + end])
+    }
+
+    /// From buffer find the longest token starting at pointer, move pointer
+    /// past it and return its value
+    pub fn find_longest(&self, buffer: &[u8], pointer: &mut usize) -> usize {
+        let bytes = self.data.as_ref();
+
+        let mut node = read_u32(bytes, HEADER_LEN, buffer[*pointer] as usize);
+        if node == NO_NODE {
+            panic!("no child in tokenizer that matches");
+        }
+        let mut end = *pointer + 1;
+        let mut longest = None;
+
+        // keep going past nodes that are not tokens, then fall back to the last token
+        loop {
+            let token_value = read_u32(bytes, self.node_tokens, node as usize);
+            if token_value != NO_TOKEN {
+                longest = Some((token_value, end));
+            }
+            let byte = match buffer.get(end) {
+                None => break,
+                Some(byte) => byte,
+            };
+
+            let start = read_u32(bytes, self.child_offsets, node as usize) as usize;
+            let stop = read_u32(bytes, self.child_offsets, node as usize + 1) as usize;
+            let labels = &bytes[self.child_labels + start..self.child_labels + stop];
+            match labels.binary_search(byte) {
+                Err(_) => break,
+                Ok(i) => {
+                    node = read_u32(bytes, self.child_targets, start + i);
+                    end += 1;
+                }
+            }
+        }
+
+        match longest {
+            None => panic!("no token starts with {:?}", buffer[*pointer]),
+            Some((token_value, end)) => {
+                *pointer = end;
+                token_value as usize
+            }
+        }
+    }
+
+    pub fn tokenize(&self, read_buffer: &[u8], write_buffer: &mut Vec<usize>, pointer: &mut usize) {
+        while *pointer < read_buffer.len() {
+            write_buffer.push(self.find_longest(read_buffer, pointer));
+        }
+    }
+
+    pub fn detokenize(&self, read_buffer: &[usize], write_buffer: &mut Vec<u8>) {
+        for elem in read_buffer {
+            write_buffer.extend_from_slice(self.token_bytes(*elem).unwrap());
+        }
+    }
+}
+
+/// Read the index-th u32 of the section starting at offset
+fn read_u32(bytes: &[u8], offset: usize, index: usize) -> u32 {
+    let start = offset + index * 4;
+    u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{write_mapped, MappedTokenizer};
+    use crate::{
+        bytes::ByteTokenizer, generate, temp_path::TempPath, test_data::RAW_TEXT, Tokenizer,
+    };
+
+    #[test]
+    fn mapped_matches_byte_tokenizer() {
+        let bytes: Vec<u8> = RAW_TEXT.bytes().take(4000).collect();
+        let tokenizer = generate(&bytes, 160);
+        let byte_tokenizer = ByteTokenizer::from(&tokenizer);
+
+        let mut expected: Vec<usize> = vec![];
+        byte_tokenizer.tokenize(&bytes, &mut expected, &mut 0);
+
+        let path = TempPath::new("bpe_mapped");
+        let mut file = fs::File::create(&path).unwrap();
+        write_mapped(&byte_tokenizer, &mut file).unwrap();
+        drop(file);
+
+        let mapped = MappedTokenizer::open(&path).unwrap();
+        let mut token_buffer: Vec<usize> = vec![];
+        mapped.tokenize(&bytes, &mut token_buffer, &mut 0);
+        assert_eq!(expected, token_buffer);
+
+        let mut detokenized = vec![];
+        mapped.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(bytes, detokenized);
+
+        // truncated content is refused
+        let mut buffer = vec![];
+        write_mapped(&byte_tokenizer, &mut buffer).unwrap();
+        buffer.pop();
+        assert!(MappedTokenizer::from_bytes(&buffer).is_err());
+
+        // version 1 files are refused
+        buffer.push(0);
+        buffer[4..8].copy_from_slice(&1u32.to_le_bytes());
+        assert!(MappedTokenizer::from_bytes(&buffer).is_err());
+
+        // a tokenizer without lookup offsets is written as an empty one
+        let mut empty = ByteTokenizer::new(&Tokenizer::default());
+        empty.lookup_offsets.clear();
+        let mut buffer = vec![];
+        write_mapped(&empty, &mut buffer).unwrap();
+        let mapped = MappedTokenizer::from_bytes(buffer).unwrap();
+        assert_eq!(mapped.token_bytes(0), None);
+    }
+}
@@ -0,0 +1,464 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Event tokens of a MIDI performance, usable as T in Tokenizer<T>. Notes
+/// carry no velocity or time, those are set by the Velocity and TimeShift
+/// events before them, which keeps the base vocabulary small.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MidiEvent {
+    NoteOn {
+        channel: u8,
+        note: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    /// Velocity bin used by the following NoteOn events
+    Velocity(u8),
+    /// Moves time forward by this many steps, at most MidiConfig::max_time_shift
+    TimeShift(u16),
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    /// Beats per minute, rounded
+    Tempo(u16),
+}
+
+/// Messages kept from a Standard MIDI File, everything else is dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Message {
+    NoteOn {
+        channel: u8,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: u8,
+        note: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    /// Microseconds per quarter note
+    Tempo(u32),
+}
+
+/// How Standard MIDI Files are turned into MidiEvent and back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MidiConfig {
+    /// Time resolution, also the division of written files
+    pub steps_per_quarter: u16,
+    /// Number of Velocity values, velocities 0-127 are split evenly
+    pub velocity_bins: u8,
+    /// Longer pauses become several TimeShift events
+    pub max_time_shift: u16,
+}
+
+impl Default for MidiConfig {
+    fn default() -> Self {
+        MidiConfig {
+            steps_per_quarter: 12,
+            velocity_bins: 32,
+            max_time_shift: 48,
+        }
+    }
+}
+
+impl MidiConfig {
+    pub fn velocity_bin(&self, velocity: u8) -> u8 {
+        (velocity.min(127) as u32 * self.velocity_bins as u32 / 128) as u8
+    }
+
+    /// Middle of the bin, never 0 since that would be a note off
+    pub fn bin_velocity(&self, bin: u8) -> u8 {
+        let velocity = (2 * bin as u32 + 1) * 128 / (2 * self.velocity_bins as u32);
+        velocity.clamp(1, 127) as u8
+    }
+
+    /// Read a format 0 or 1 file, all tracks merged in time order. Format 2
+    /// tracks are independent sequences and are refused.
+    pub fn to_events(&self, smf: &[u8]) -> Result<Vec<MidiEvent>, Error> {
+        let mut reader = Reader::new(smf);
+        if reader.take(4)? != b"MThd" {
+            return Err(Error::Format("not a midi file".to_string()));
+        }
+        let header = reader.chunk()?;
+        if header.len() < 6 {
+            return Err(Error::Format("midi header too short".to_string()));
+        }
+        let format = u16::from_be_bytes([header[0], header[1]]);
+        if format > 1 {
+            return Err(Error::Format(format!(
+                "midi format {} is not supported",
+                format
+            )));
+        }
+        let division = u16::from_be_bytes([header[4], header[5]]);
+        if division & 0x8000 != 0 || division == 0 {
+            return Err(Error::Format(
+                "smpte time division is not supported".to_string(),
+            ));
+        }
+
+        let mut messages: Vec<(u64, Message)> = vec![];
+        while !reader.is_done() {
+            let id = reader.take(4)?;
+            let chunk = reader.chunk()?;
+            // other chunk types are allowed and ignored
+            if id == b"MTrk" {
+                read_track(chunk, &mut messages)?;
+            }
+        }
+        // stable, simultaneous messages keep their track order
+        messages.sort_by_key(|(tick, _)| *tick);
+
+        let mut events = vec![];
+        let mut step: u64 = 0;
+        let mut velocity = None;
+        for (tick, message) in messages {
+            let at = (tick * self.steps_per_quarter as u64 + division as u64 / 2) / division as u64;
+            let mut shift = at - step;
+            while shift > 0 {
+                let chunk = shift.min(self.max_time_shift as u64);
+                events.push(MidiEvent::TimeShift(chunk as u16));
+                shift -= chunk;
+            }
+            step = at;
+
+            match message {
+                Message::NoteOn {
+                    channel,
+                    note,
+                    velocity: note_velocity,
+                } => {
+                    let bin = self.velocity_bin(note_velocity);
+                    if velocity != Some(bin) {
+                        events.push(MidiEvent::Velocity(bin));
+                        velocity = Some(bin);
+                    }
+                    events.push(MidiEvent::NoteOn { channel, note });
+                }
+                Message::NoteOff { channel, note } => {
+                    events.push(MidiEvent::NoteOff { channel, note })
+                }
+                Message::ProgramChange { channel, program } => {
+                    events.push(MidiEvent::ProgramChange { channel, program })
+                }
+                Message::Tempo(micros) => {
+                    let bpm = (60_000_000 + micros as u64 / 2) / micros.max(1) as u64;
+                    events.push(MidiEvent::Tempo(bpm.clamp(1, u16::MAX as u64) as u16));
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Format 0 file with one tick per step. Notes before any Velocity use the
+    /// middle bin. Tempos below 4 bpm are written as the slowest tempo
+    /// the 24 bit field holds.
+    pub fn to_smf(&self, events: &[MidiEvent]) -> Vec<u8> {
+        let mut track = vec![];
+        let mut delta: u32 = 0;
+        let mut velocity = self.bin_velocity(self.velocity_bins / 2);
+
+        for event in events {
+            let message: Vec<u8> = match event {
+                MidiEvent::TimeShift(steps) => {
+                    delta += *steps as u32;
+                    continue;
+                }
+                MidiEvent::Velocity(bin) => {
+                    velocity = self.bin_velocity(*bin);
+                    continue;
+                }
+                MidiEvent::NoteOn { channel, note } => vec![0x90 | channel, *note, velocity],
+                MidiEvent::NoteOff { channel, note } => vec![0x80 | channel, *note, 0x40],
+                MidiEvent::ProgramChange { channel, program } => vec![0xC0 | channel, *program],
+                MidiEvent::Tempo(bpm) => {
+                    let micros = (60_000_000 / (*bpm).max(1) as u32).min(0xFF_FFFF);
+                    let micros = micros.to_be_bytes();
+                    vec![0xFF, 0x51, 0x03, micros[1], micros[2], micros[3]]
+                }
+            };
+            write_var_len(delta, &mut track);
+            track.extend_from_slice(&message);
+            delta = 0;
+        }
+        write_var_len(delta, &mut track);
+        track.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+
+        let mut smf = vec![];
+        smf.extend_from_slice(b"MThd");
+        smf.extend_from_slice(&6u32.to_be_bytes());
+        smf.extend_from_slice(&0u16.to_be_bytes());
+        smf.extend_from_slice(&1u16.to_be_bytes());
+        smf.extend_from_slice(&self.steps_per_quarter.to_be_bytes());
+        smf.extend_from_slice(b"MTrk");
+        smf.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        smf.extend_from_slice(&track);
+        smf
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<MidiEvent>, Error> {
+        self.to_events(&fs::read(path)?)
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, events: &[MidiEvent], path: P) -> Result<(), Error> {
+        fs::write(path, self.to_smf(events))?;
+        Ok(())
+    }
+}
+
+/// Absolute tick and message of every kept event of a track
+fn read_track(track: &[u8], messages: &mut Vec<(u64, Message)>) -> Result<(), Error> {
+    let mut reader = Reader::new(track);
+    let mut tick: u64 = 0;
+    let mut running_status = None;
+
+    while !reader.is_done() {
+        tick += reader.var_len()? as u64;
+
+        let mut status = reader.u8()?;
+        if status < 0x80 {
+            // running status, the byte was the first data byte
+            status = running_status
+                .ok_or_else(|| Error::Format("data byte without status".to_string()))?;
+            reader.pointer -= 1;
+        }
+
+        match status {
+            0xFF => {
+                let kind = reader.u8()?;
+                let len = reader.var_len()? as usize;
+                let data = reader.take(len)?;
+                match (kind, data) {
+                    (0x51, [a, b, c]) => {
+                        let micros = u32::from_be_bytes([0, *a, *b, *c]);
+                        messages.push((tick, Message::Tempo(micros)));
+                    }
+                    // end of track
+                    (0x2F, _) => break,
+                    _ => (),
+                }
+            }
+            0xF0 | 0xF7 => {
+                let len = reader.var_len()? as usize;
+                reader.take(len)?;
+                running_status = None;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                match status & 0xF0 {
+                    0x80 => {
+                        let data = reader.take(2)?;
+                        messages.push((
+                            tick,
+                            Message::NoteOff {
+                                channel,
+                                note: data[0],
+                            },
+                        ));
+                    }
+                    0x90 => {
+                        let data = reader.take(2)?;
+                        let message = match data[1] {
+                            0 => Message::NoteOff {
+                                channel,
+                                note: data[0],
+                            },
+                            velocity => Message::NoteOn {
+                                channel,
+                                note: data[0],
+                                velocity,
+                            },
+                        };
+                        messages.push((tick, message));
+                    }
+                    0xC0 => {
+                        let program = reader.u8()?;
+                        messages.push((tick, Message::ProgramChange { channel, program }));
+                    }
+                    0xD0 => {
+                        reader.take(1)?;
+                    }
+                    // aftertouch, controllers and pitch bend
+                    _ => {
+                        reader.take(2)?;
+                    }
+                }
+            }
+            _ => {
+                return Err(Error::Format(format!(
+                    "unexpected status {:#04x} in track",
+                    status
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_var_len(value: u32, out: &mut Vec<u8>) {
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        groups.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(groups.iter().rev());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pointer: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pointer: 0 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pointer >= self.bytes.len()
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let data = self
+            .bytes
+            .get(self.pointer..self.pointer + len)
+            .ok_or_else(|| Error::Format("truncated midi file".to_string()))?;
+        self.pointer += len;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Length prefixed chunk body
+    fn chunk(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.take(4)?;
+        self.take(u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+    }
+
+    fn var_len(&mut self) -> Result<u32, Error> {
+        let mut value: u32 = 0;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Format(
+            "variable length quantity too long".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_var_len, MidiConfig, MidiEvent};
+
+    fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(body);
+        chunk
+    }
+
+    /// Format 1, 96 ticks per quarter, a tempo track and a note track using
+    /// running status and note on with velocity 0 as note off
+    fn synthetic_smf() -> Vec<u8> {
+        let mut smf = chunk(b"MThd", &[0, 1, 0, 2, 0, 96]);
+        smf.extend(chunk(
+            b"MTrk",
+            &[
+                0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, 0x00, 0xFF, 0x2F, 0x00,
+            ],
+        ));
+        let mut notes = vec![0x00, 0xC1, 0x18, 0x00, 0x91, 60, 100, 0x00, 64, 90];
+        notes.extend_from_slice(&[0x30, 60, 0, 0x00, 64, 0]);
+        notes.extend_from_slice(&[0x00, 0xB1, 0x07, 0x64]);
+        write_var_len(96 * 5, &mut notes);
+        notes.extend_from_slice(&[0x81, 67, 0x40, 0x00, 0xFF, 0x2F, 0x00]);
+        smf.extend(chunk(b"MTrk", &notes));
+        smf
+    }
+
+    #[test]
+    fn smf_to_events() {
+        let config = MidiConfig::default();
+        let events = config.to_events(&synthetic_smf()).unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                MidiEvent::Tempo(120),
+                MidiEvent::ProgramChange {
+                    channel: 1,
+                    program: 0x18
+                },
+                MidiEvent::Velocity(25),
+                MidiEvent::NoteOn {
+                    channel: 1,
+                    note: 60
+                },
+                MidiEvent::Velocity(22),
+                MidiEvent::NoteOn {
+                    channel: 1,
+                    note: 64
+                },
+                MidiEvent::TimeShift(6),
+                MidiEvent::NoteOff {
+                    channel: 1,
+                    note: 60
+                },
+                MidiEvent::NoteOff {
+                    channel: 1,
+                    note: 64
+                },
+                MidiEvent::TimeShift(48),
+                MidiEvent::TimeShift(12),
+                MidiEvent::NoteOff {
+                    channel: 1,
+                    note: 67
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip_through_smf() {
+        let config = MidiConfig {
+            velocity_bins: 10,
+            ..MidiConfig::default()
+        };
+        let events = config.to_events(&synthetic_smf()).unwrap();
+
+        let smf = config.to_smf(&events);
+        assert_eq!(&smf[..4], b"MThd");
+        assert_eq!(config.to_events(&smf).unwrap(), events);
+
+        for bin in 0..config.velocity_bins {
+            assert_eq!(config.velocity_bin(config.bin_velocity(bin)), bin);
+        }
+
+        // 24 bits of microseconds per quarter go down to about 3.6 bpm
+        let slow = config.to_smf(&[MidiEvent::Tempo(1)]);
+        assert_eq!(config.to_events(&slow).unwrap(), vec![MidiEvent::Tempo(4)]);
+
+        let mut format_2 = synthetic_smf();
+        format_2[9] = 2;
+        assert!(config.to_events(&format_2).is_err());
+    }
+}
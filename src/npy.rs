@@ -0,0 +1,293 @@
+use std::{fmt::Debug, hash::Hash, io::Write};
+
+use crate::{
+    dataset::{check_elements, TokenWidth},
+    error::Error,
+    Tokenizer,
+};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+/// numpy pads headers so the data starts on this boundary
+const ALIGNMENT: usize = 64;
+
+/// Header of a version 1.0 `.npy` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpyHeader {
+    /// numpy dtype string, e.g. `<u2`
+    pub descr: String,
+    pub fortran_order: bool,
+    pub shape: Vec<usize>,
+}
+
+impl NpyHeader {
+    pub fn new(descr: &str, shape: &[usize]) -> Self {
+        NpyHeader {
+            descr: descr.to_owned(),
+            fortran_order: false,
+            shape: shape.to_vec(),
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        let shape = match &self.shape[..] {
+            [len] => format!("({},)", len),
+            shape => format!(
+                "({})",
+                shape
+                    .iter()
+                    .map(|len| len.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let fortran_order = if self.fortran_order { "True" } else { "False" };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': {}, 'shape': {}, }}",
+            self.descr, fortran_order, shape
+        );
+
+        // magic, version and header length take 10 bytes, the header ends with a newline
+        let padding = (ALIGNMENT - (10 + header.len() + 1) % ALIGNMENT) % ALIGNMENT;
+        header.push_str(&" ".repeat(padding));
+        header.push('\n');
+        let header_len = u16::try_from(header.len())
+            .map_err(|_| Error::Format("npy header too long".to_string()))?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[1, 0])?;
+        writer.write_all(&header_len.to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        Ok(())
+    }
+
+    /// Parse the header at the start of a file, returns it with the offset of
+    /// the array data
+    pub fn parse(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        if bytes.len() < 10 || &bytes[..6] != MAGIC {
+            return Err(Error::Format("not a npy file".to_string()));
+        }
+        // version 1 has a u16 header length, later versions a u32
+        let (header_start, header_len) = match bytes[6] {
+            1 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+            2 | 3 if bytes.len() >= 12 => (
+                12,
+                u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+            ),
+            version => {
+                return Err(Error::Format(format!(
+                    "unsupported npy version {}",
+                    version
+                )))
+            }
+        };
+        let header = bytes
+            .get(header_start..header_start + header_len)
+            .and_then(|header| std::str::from_utf8(header).ok())
+            .ok_or_else(|| Error::Format("invalid npy header".to_string()))?;
+
+        let descr = dict_value(header, "descr")?
+            .trim_matches(|c| c == '\'' || c == '"')
+            .to_owned();
+        let fortran_order = match dict_value(header, "fortran_order")? {
+            "True" => true,
+            "False" => false,
+            value => return Err(Error::Format(format!("invalid fortran_order {}", value))),
+        };
+        let shape = dict_value(header, "shape")?
+            .trim_start_matches('(')
+            .trim_end_matches(')')
+            .split(',')
+            .map(|len| len.trim())
+            .filter(|len| !len.is_empty())
+            .map(|len| {
+                len.parse()
+                    .map_err(|_| Error::Format(format!("invalid shape entry {}", len)))
+            })
+            .collect::<Result<Vec<usize>, Error>>()?;
+
+        Ok((
+            NpyHeader {
+                descr,
+                fortran_order,
+                shape,
+            },
+            header_start + header_len,
+        ))
+    }
+}
+
+/// Raw text of the value of key in the header dict literal
+fn dict_value<'a>(header: &'a str, key: &str) -> Result<&'a str, Error> {
+    let missing = || Error::Format(format!("npy header has no {}", key));
+    let start = header.find(&format!("'{}':", key)).ok_or_else(missing)? + key.len() + 3;
+    let rest = header[start..].trim_start();
+
+    // tuples hold commas, everything else ends at the next one
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|end| end + 1)
+    } else {
+        rest.find([',', '}'])
+    };
+    Ok(rest[..end.ok_or_else(missing)?].trim())
+}
+
+fn descr(width: TokenWidth) -> &'static str {
+    match width {
+        TokenWidth::U16 => "<u2",
+        TokenWidth::U32 => "<u4",
+    }
+}
+
+fn encode<T>(tokenizer: &Tokenizer<T>, documents: &[Vec<T>]) -> Result<Vec<Vec<usize>>, Error>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    documents
+        .iter()
+        .map(|document| {
+            check_elements(tokenizer, document)?;
+
+            let mut token_buffer: Vec<usize> = vec![];
+            tokenizer.tokenize(document, &mut token_buffer, &mut 0);
+            Ok(token_buffer)
+        })
+        .collect()
+}
+
+/// 1-D array of every document's tokens one after the other, u16 or u32
+/// depending on the vocabulary, and a 1-D `<u8` array of the document
+/// offsets with the total length last. Fails on elements outside the
+/// vocabulary before writing anything.
+pub fn write_concatenated<T, W, O>(
+    tokenizer: &Tokenizer<T>,
+    documents: &[Vec<T>],
+    tokens_writer: &mut W,
+    offsets_writer: &mut O,
+) -> Result<(), Error>
+where
+    T: Eq + Hash + Clone + Debug,
+    W: Write,
+    O: Write,
+{
+    let width = TokenWidth::for_tokenizer(tokenizer);
+    let encoded = encode(tokenizer, documents)?;
+
+    let mut offsets: Vec<u64> = vec![0];
+    for token_buffer in &encoded {
+        offsets.push(offsets.last().unwrap() + token_buffer.len() as u64);
+    }
+
+    NpyHeader::new(descr(width), &[*offsets.last().unwrap() as usize]).write(tokens_writer)?;
+    for token_value in encoded.iter().flatten() {
+        width.write(*token_value, tokens_writer)?;
+    }
+
+    NpyHeader::new("<u8", &[offsets.len()]).write(offsets_writer)?;
+    for offset in offsets {
+        offsets_writer.write_all(&offset.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// 2-D array with a row per document, shorter documents are filled with
+/// pad_value. u32 is used if the vocabulary or pad_value need it. Fails on
+/// elements outside the vocabulary before writing anything.
+pub fn write_padded<T, W>(
+    tokenizer: &Tokenizer<T>,
+    documents: &[Vec<T>],
+    pad_value: usize,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    T: Eq + Hash + Clone + Debug,
+    W: Write,
+{
+    let width = match TokenWidth::for_tokenizer(tokenizer) {
+        TokenWidth::U16 if pad_value <= u16::MAX as usize => TokenWidth::U16,
+        _ => TokenWidth::U32,
+    };
+    let encoded = encode(tokenizer, documents)?;
+    let columns = encoded
+        .iter()
+        .map(|token_buffer| token_buffer.len())
+        .max()
+        .unwrap_or(0);
+
+    NpyHeader::new(descr(width), &[encoded.len(), columns]).write(writer)?;
+    for token_buffer in &encoded {
+        for token_value in token_buffer {
+            width.write(*token_value, writer)?;
+        }
+        for _ in token_buffer.len()..columns {
+            width.write(pad_value, writer)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_concatenated, write_padded, NpyHeader};
+    use crate::{error::Error, generate, test_data::RAW_TEXT};
+
+    #[test]
+    fn parse_written_headers() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 96);
+        let pad = tokenizer.add_special_token("<pad>");
+        let documents: Vec<Vec<char>> = text_val.chunks(300).map(|chunk| chunk.to_vec()).collect();
+
+        let mut tokens = vec![];
+        let mut offsets = vec![];
+        write_concatenated(&tokenizer, &documents, &mut tokens, &mut offsets).unwrap();
+
+        let (header, data_start) = NpyHeader::parse(&tokens).unwrap();
+        assert_eq!(data_start % 64, 0);
+        assert_eq!(header.descr, "<u2");
+        assert!(!header.fortran_order);
+        let (offsets_header, offsets_start) = NpyHeader::parse(&offsets).unwrap();
+        assert_eq!(
+            offsets_header,
+            NpyHeader::new("<u8", &[documents.len() + 1])
+        );
+        assert_eq!(tokens.len() - data_start, header.shape[0] * 2);
+
+        let mut expected: Vec<usize> = vec![];
+        for document in &documents {
+            tokenizer.tokenize(document, &mut expected, &mut 0);
+        }
+        let payload: Vec<usize> = tokens[data_start..]
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]) as usize)
+            .collect();
+        assert_eq!(payload, expected);
+
+        let last = &offsets[offsets.len() - 8..];
+        assert_eq!(
+            u64::from_le_bytes(last.try_into().unwrap()) as usize,
+            header.shape[0]
+        );
+        assert_eq!(offsets.len() - offsets_start, (documents.len() + 1) * 8);
+
+        let mut padded = vec![];
+        write_padded(&tokenizer, &documents, pad, &mut padded).unwrap();
+        let (header, data_start) = NpyHeader::parse(&padded).unwrap();
+        assert_eq!(header.shape[0], documents.len());
+        assert_eq!(
+            padded.len() - data_start,
+            header.shape[0] * header.shape[1] * 2
+        );
+
+        // the last document is the shortest, its row ends with padding
+        let last = &padded[padded.len() - 2..];
+        assert_eq!(u16::from_le_bytes([last[0], last[1]]) as usize, pad);
+
+        let unknown = vec![vec!['\u{1F3B5}']];
+        let mut written = vec![];
+        assert!(matches!(
+            write_padded(&tokenizer, &unknown, pad, &mut written),
+            Err(Error::Format(_))
+        ));
+        assert!(written.is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+use std::{collections::HashMap, fmt::Debug, hash::Hash};
+
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    bytes::ByteTokenizer,
+    dataset::check_elements,
+    error::Error,
+    files::{load, save, Format},
+    with_rayon::{parallel_generate_bytes, parallel_generate_with_max_length},
+    Tokenizer,
+};
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => PyIOError::new_err(err.to_string()),
+            err => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// ValueError instead of the panic detokenize raises on unknown values
+fn check_token_values<T>(tokenizer: &Tokenizer<T>, ids: &[usize]) -> PyResult<()>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    match ids.iter().find(|token_value| {
+        !tokenizer.lookup.contains_key(token_value) && !tokenizer.is_special_token(**token_value)
+    }) {
+        Some(token_value) => Err(PyValueError::new_err(format!(
+            "unknown token value {}",
+            token_value
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Tokenizer over the characters of str
+#[pyclass(name = "CharTokenizer")]
+pub struct PyCharTokenizer {
+    tokenizer: Tokenizer<char>,
+}
+
+#[pymethods]
+impl PyCharTokenizer {
+    /// Train on texts, the base vocabulary is every character they hold
+    #[staticmethod]
+    #[pyo3(signature = (texts, vocab_size, max_length=None))]
+    fn train(
+        py: Python<'_>,
+        texts: Vec<String>,
+        vocab_size: usize,
+        max_length: Option<usize>,
+    ) -> Self {
+        let tokenizer = py.detach(|| {
+            let inputs: Vec<Vec<char>> = texts.iter().map(|text| text.chars().collect()).collect();
+            let mut base_vocabulary: Vec<char> = inputs.iter().flatten().copied().collect();
+            base_vocabulary.sort_unstable();
+            base_vocabulary.dedup();
+
+            parallel_generate_with_max_length(
+                inputs,
+                base_vocabulary,
+                vocab_size,
+                max_length.unwrap_or(usize::MAX),
+            )
+        });
+        PyCharTokenizer { tokenizer }
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (path, unchecked=false))]
+    fn load(path: &str, unchecked: bool) -> PyResult<Self> {
+        Ok(PyCharTokenizer {
+            tokenizer: load(path, unchecked)?,
+        })
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        Ok(save(&self.tokenizer, path, Format::from_path(path))?)
+    }
+
+    fn encode(&self, text: &str) -> PyResult<Vec<usize>> {
+        let input: Vec<char> = text.chars().collect();
+        check_elements(&self.tokenizer, &input)?;
+
+        let mut token_buffer: Vec<usize> = vec![];
+        self.tokenizer.tokenize(&input, &mut token_buffer, &mut 0);
+        Ok(token_buffer)
+    }
+
+    /// Encode texts in parallel without holding the GIL
+    fn encode_batch(&self, py: Python<'_>, texts: Vec<String>) -> PyResult<Vec<Vec<usize>>> {
+        py.detach(|| {
+            texts
+                .par_iter()
+                .map(|text| {
+                    let input: Vec<char> = text.chars().collect();
+                    check_elements(&self.tokenizer, &input)?;
+
+                    let mut token_buffer: Vec<usize> = vec![];
+                    self.tokenizer.tokenize(&input, &mut token_buffer, &mut 0);
+                    Ok(token_buffer)
+                })
+                .collect()
+        })
+    }
+
+    /// Special tokens are skipped
+    fn decode(&self, ids: Vec<usize>) -> PyResult<String> {
+        check_token_values(&self.tokenizer, &ids)?;
+
+        let mut detokenized: Vec<char> = vec![];
+        self.tokenizer.detokenize(&ids, &mut detokenized);
+        Ok(detokenized.into_iter().collect())
+    }
+
+    fn add_special_token(&mut self, name: &str) -> usize {
+        self.tokenizer.add_special_token(name)
+    }
+
+    fn special_token(&self, name: &str) -> Option<usize> {
+        self.tokenizer.special_token(name)
+    }
+
+    #[getter]
+    fn special_tokens(&self) -> HashMap<String, usize> {
+        self.tokenizer.special_tokens.clone()
+    }
+
+    /// Token value to text, special tokens excluded
+    fn vocabulary(&self) -> HashMap<usize, String> {
+        self.tokenizer
+            .lookup
+            .iter()
+            .map(|(token_value, token)| (*token_value, token.iter().collect()))
+            .collect()
+    }
+
+    #[getter]
+    fn vocab_size(&self) -> usize {
+        self.tokenizer.vocabulary_size()
+    }
+
+    fn __len__(&self) -> usize {
+        self.tokenizer.vocabulary_size()
+    }
+}
+
+/// Byte level tokenizer over bytes, every byte value is in the vocabulary
+#[pyclass(name = "ByteTokenizer")]
+pub struct PyByteTokenizer {
+    tokenizer: Tokenizer<u8>,
+    /// Compiled encoder, special tokens never change it
+    compiled: ByteTokenizer,
+}
+
+impl PyByteTokenizer {
+    fn new(tokenizer: Tokenizer<u8>) -> Self {
+        let compiled = ByteTokenizer::new(&tokenizer);
+        PyByteTokenizer {
+            tokenizer,
+            compiled,
+        }
+    }
+}
+
+#[pymethods]
+impl PyByteTokenizer {
+    #[staticmethod]
+    #[pyo3(signature = (data, vocab_size, max_length=None))]
+    fn train(
+        py: Python<'_>,
+        data: Vec<Bound<'_, PyBytes>>,
+        vocab_size: usize,
+        max_length: Option<usize>,
+    ) -> Self {
+        let inputs: Vec<Vec<u8>> = data.iter().map(|data| data.as_bytes().to_vec()).collect();
+        let tokenizer = py.detach(|| {
+            parallel_generate_bytes(inputs, vocab_size, max_length.unwrap_or(usize::MAX))
+        });
+        PyByteTokenizer::new(tokenizer)
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (path, unchecked=false))]
+    fn load(path: &str, unchecked: bool) -> PyResult<Self> {
+        Ok(PyByteTokenizer::new(load(path, unchecked)?))
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        Ok(save(&self.tokenizer, path, Format::from_path(path))?)
+    }
+
+    fn encode(&self, data: &[u8]) -> PyResult<Vec<usize>> {
+        check_elements(&self.tokenizer, data)?;
+
+        let mut token_buffer: Vec<usize> = vec![];
+        self.compiled.tokenize(data, &mut token_buffer, &mut 0);
+        Ok(token_buffer)
+    }
+
+    /// Encode byte strings in parallel without holding the GIL
+    fn encode_batch(
+        &self,
+        py: Python<'_>,
+        data: Vec<Bound<'_, PyBytes>>,
+    ) -> PyResult<Vec<Vec<usize>>> {
+        let inputs: Vec<Vec<u8>> = data.iter().map(|data| data.as_bytes().to_vec()).collect();
+        py.detach(|| {
+            inputs
+                .par_iter()
+                .map(|input| {
+                    check_elements(&self.tokenizer, input)?;
+
+                    let mut token_buffer: Vec<usize> = vec![];
+                    self.compiled.tokenize(input, &mut token_buffer, &mut 0);
+                    Ok(token_buffer)
+                })
+                .collect()
+        })
+    }
+
+    /// Special tokens are skipped
+    fn decode<'py>(&self, py: Python<'py>, ids: Vec<usize>) -> PyResult<Bound<'py, PyBytes>> {
+        check_token_values(&self.tokenizer, &ids)?;
+
+        let mut detokenized: Vec<u8> = vec![];
+        self.tokenizer.detokenize(&ids, &mut detokenized);
+        Ok(PyBytes::new(py, &detokenized))
+    }
+
+    fn add_special_token(&mut self, name: &str) -> usize {
+        self.tokenizer.add_special_token(name)
+    }
+
+    fn special_token(&self, name: &str) -> Option<usize> {
+        self.tokenizer.special_token(name)
+    }
+
+    #[getter]
+    fn special_tokens(&self) -> HashMap<String, usize> {
+        self.tokenizer.special_tokens.clone()
+    }
+
+    /// Token value to bytes, special tokens excluded
+    fn vocabulary<'py>(&self, py: Python<'py>) -> HashMap<usize, Bound<'py, PyBytes>> {
+        self.tokenizer
+            .lookup
+            .iter()
+            .map(|(token_value, token)| (*token_value, PyBytes::new(py, token)))
+            .collect()
+    }
+
+    #[getter]
+    fn vocab_size(&self) -> usize {
+        self.tokenizer.vocabulary_size()
+    }
+
+    fn __len__(&self) -> usize {
+        self.tokenizer.vocabulary_size()
+    }
+}
+
+#[pymodule]
+fn bpe_tokenizer(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyCharTokenizer>()?;
+    module.add_class::<PyByteTokenizer>()?;
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use std::fmt;
+
+use crate::{error::Error, vgm::VgmEvent};
+
+/// Wait lengths, in samples, that VGM waits are snapped to before training.
+/// A wait becomes a sum of bins, so the base vocabulary holds at most one
+/// Wait per bin instead of one per distinct wait value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitBins {
+    /// Ascending, no zero
+    bins: Vec<u32>,
+}
+
+impl Default for WaitBins {
+    /// Powers of two up to 32768 plus the 60 Hz and 50 Hz frame waits
+    fn default() -> Self {
+        let mut bins: Vec<u32> = (0..16).map(|shift| 1 << shift).collect();
+        bins.extend_from_slice(&[735, 882]);
+        WaitBins::new(bins).unwrap()
+    }
+}
+
+impl WaitBins {
+    pub fn new(mut bins: Vec<u32>) -> Result<Self, Error> {
+        bins.sort_unstable();
+        bins.dedup();
+        if bins.is_empty() || bins[0] == 0 {
+            return Err(Error::Format(
+                "wait bins must be non empty and above 0".to_string(),
+            ));
+        }
+        Ok(WaitBins { bins })
+    }
+
+    pub fn bins(&self) -> &[u32] {
+        &self.bins
+    }
+
+    /// Split samples into bins, largest first. A remainder below the smallest
+    /// bin is rounded to the nearest of 0 and the smallest bin.
+    pub fn split(&self, mut samples: u32) -> Vec<u32> {
+        let mut parts = vec![];
+        for bin in self.bins.iter().rev() {
+            while samples >= *bin {
+                parts.push(*bin);
+                samples -= bin;
+            }
+        }
+        if samples > 0 && samples as u64 * 2 >= self.bins[0] as u64 {
+            parts.push(self.bins[0]);
+        }
+        parts
+    }
+
+    /// Replace every run of waits by binned waits. Rounding errors are carried
+    /// over to the next run, so the quantized stream never drifts from the
+    /// original by more than half the smallest bin.
+    pub fn quantize(&self, events: &[VgmEvent]) -> (Vec<VgmEvent>, QuantizationReport) {
+        let mut quantized = Vec::with_capacity(events.len());
+        let mut report = QuantizationReport::default();
+
+        let mut pending: u64 = 0;
+        for event in events {
+            if let VgmEvent::Wait(samples) = event {
+                pending += *samples as u64;
+                continue;
+            }
+
+            self.quantize_run(pending, &mut quantized, &mut report);
+            pending = 0;
+
+            // DAC waits are kept as is
+            report.total_samples += event.samples() as u64;
+            report.quantized_samples += event.samples() as u64;
+            quantized.push(event.to_owned());
+        }
+        self.quantize_run(pending, &mut quantized, &mut report);
+
+        (quantized, report)
+    }
+
+    /// The report's sample counts are the original and quantized clocks
+    fn quantize_run(
+        &self,
+        samples: u64,
+        quantized: &mut Vec<VgmEvent>,
+        report: &mut QuantizationReport,
+    ) {
+        if samples == 0 {
+            return;
+        }
+        report.waits += 1;
+        report.total_samples += samples;
+
+        // what is left to reach the original clock, nothing if already ahead
+        let target = report
+            .total_samples
+            .saturating_sub(report.quantized_samples);
+        let mut emitted: u64 = 0;
+        for chunk in split_u64(target) {
+            for part in self.split(chunk) {
+                quantized.push(VgmEvent::Wait(part));
+                emitted += part as u64;
+            }
+        }
+        report.quantized_samples += emitted;
+
+        let error = emitted.abs_diff(samples);
+        if error == 0 {
+            report.exact_waits += 1;
+        }
+        report.max_error = report.max_error.max(error);
+        report.total_error += error;
+        report.max_drift = report
+            .max_drift
+            .max(report.quantized_samples.abs_diff(report.total_samples));
+    }
+}
+
+/// Waits above u32::MAX samples cannot come from a file, but summed runs are u64
+fn split_u64(mut samples: u64) -> Vec<u32> {
+    let mut chunks = vec![];
+    while samples > 0 {
+        let chunk = samples.min(u32::MAX as u64);
+        chunks.push(chunk as u32);
+        samples -= chunk;
+    }
+    chunks
+}
+
+/// Reverse of WaitBins::quantize, runs of binned waits are summed back into a
+/// single Wait so VgmFile::to_bytes writes them compactly
+pub fn dequantize(events: &[VgmEvent]) -> Vec<VgmEvent> {
+    let mut merged: Vec<VgmEvent> = Vec::with_capacity(events.len());
+    for event in events {
+        match (merged.last_mut(), event) {
+            (Some(VgmEvent::Wait(previous)), VgmEvent::Wait(samples)) => {
+                match previous.checked_add(*samples) {
+                    Some(total) => *previous = total,
+                    None => merged.push(event.to_owned()),
+                }
+            }
+            _ => merged.push(event.to_owned()),
+        }
+    }
+    merged
+}
+
+/// Timing error introduced by WaitBins::quantize, in samples
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuantizationReport {
+    /// Runs of consecutive waits, each quantized as one
+    pub waits: usize,
+    /// Runs quantized without error
+    pub exact_waits: usize,
+    /// Largest difference between a run and its quantized waits
+    pub max_error: u64,
+    pub total_error: u64,
+    /// Largest difference between the original and quantized clocks
+    pub max_drift: u64,
+    pub total_samples: u64,
+    pub quantized_samples: u64,
+}
+
+impl QuantizationReport {
+    pub fn mean_error(&self) -> f64 {
+        if self.waits == 0 {
+            0.0
+        } else {
+            self.total_error as f64 / self.waits as f64
+        }
+    }
+}
+
+impl fmt::Display for QuantizationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} waits, {} exact, mean error {:.2} samples, max error {}, max drift {}, length {} -> {} samples",
+            self.waits,
+            self.exact_waits,
+            self.mean_error(),
+            self.max_error,
+            self.max_drift,
+            self.total_samples,
+            self.quantized_samples
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dequantize, WaitBins};
+    use crate::vgm::VgmEvent;
+
+    #[test]
+    fn split_into_bins() {
+        let bins = WaitBins::new(vec![735, 16, 4, 1]).unwrap();
+        assert_eq!(bins.split(735 * 2 + 21), vec![735, 735, 16, 4, 1]);
+
+        let bins = WaitBins::new(vec![64, 8]).unwrap();
+        assert_eq!(bins.split(75), vec![64, 8]);
+        assert_eq!(bins.split(76), vec![64, 8, 8]);
+        assert_eq!(bins.split(3), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn quantize_and_reverse() {
+        let note = VgmEvent::Write {
+            command: 0x52,
+            register: 0x28,
+            value: 0xF0,
+        };
+        let mut events = vec![];
+        for samples in [5, 13, 700, 29, 3, 1000] {
+            events.push(note.clone());
+            events.push(VgmEvent::Wait(samples));
+            events.push(VgmEvent::Wait(samples));
+        }
+        events.push(VgmEvent::End);
+
+        let bins = WaitBins::new(vec![8, 64, 512]).unwrap();
+        let (quantized, report) = bins.quantize(&events);
+        assert!(quantized.iter().all(|event| match event {
+            VgmEvent::Wait(samples) => bins.bins().contains(samples),
+            _ => true,
+        }));
+        assert_eq!(report.waits, 6);
+        assert_eq!(report.total_samples, 2 * (5 + 13 + 700 + 29 + 3 + 1000));
+        // carried rounding keeps the clocks within half the smallest bin
+        assert!(report.max_drift <= 4);
+        assert!(report.total_samples.abs_diff(report.quantized_samples) <= 4);
+
+        let decoded = dequantize(&quantized);
+        assert_eq!(decoded.len(), events.len() - 6);
+        assert_eq!(decoded.last(), Some(&VgmEvent::End));
+
+        // exact with a bin of 1
+        let (quantized, report) = WaitBins::default().quantize(&events);
+        assert_eq!(report.exact_waits, 6);
+        let decoded: Vec<VgmEvent> = dequantize(&quantized);
+        assert_eq!(decoded, dequantize(&events));
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashMap, hash::Hash};
+
+use crate::Merge;
+
+/// Byte pair encoding by merge rank, the way reference BPE implementations
+/// encode: start from single elements and repeatedly apply the lowest ranked
+/// merge, leftmost first. Tokenizer::tokenize takes the longest match instead,
+/// which gives the same result on vocabularies trained by this crate but not
+/// on arbitrary imported ones.
+#[derive(Debug, Clone, Default)]
+pub struct RankedMerges {
+    /// (left, right) -> (rank, merged token value)
+    ranks: HashMap<(usize, usize), (usize, usize)>,
+}
+
+impl RankedMerges {
+    /// Rank of each merge is its position in merges
+    pub fn new(merges: &[Merge]) -> Self {
+        let mut ranked = RankedMerges::default();
+        for (rank, merge) in merges.iter().enumerate() {
+            ranked.insert(merge, rank);
+        }
+        ranked
+    }
+
+    /// Every split of a token into two known tokens becomes a merge, ranked
+    /// with rank_of applied to the joined token
+    pub fn from_splits<T>(lookup: &HashMap<usize, Vec<T>>, rank_of: impl Fn(usize) -> usize) -> Self
+    where
+        T: Eq + Hash,
+    {
+        let reverse: HashMap<&[T], usize> = lookup
+            .iter()
+            .map(|(token_value, token)| (&token[..], *token_value))
+            .collect();
+
+        let mut ranked = RankedMerges::default();
+        for (token, token_value) in &reverse {
+            for split in 1..token.len() {
+                if let (Some(left), Some(right)) =
+                    (reverse.get(&token[..split]), reverse.get(&token[split..]))
+                {
+                    let merge = Merge {
+                        left: *left,
+                        right: *right,
+                        token_value: *token_value,
+                    };
+                    ranked.insert(&merge, rank_of(*token_value));
+                }
+            }
+        }
+        ranked
+    }
+
+    /// Keeps the lowest rank if the pair is already known
+    pub fn insert(&mut self, merge: &Merge, rank: usize) {
+        self.ranks
+            .entry((merge.left, merge.right))
+            .and_modify(|current| {
+                if rank < current.0 {
+                    *current = (rank, merge.token_value)
+                }
+            })
+            .or_insert((rank, merge.token_value));
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranks.is_empty()
+    }
+
+    /// Merge tokens in place until no pair has a rank. Quadratic in the
+    /// number of tokens, meant to run on words rather than whole files.
+    pub fn apply(&self, tokens: &mut Vec<usize>) {
+        loop {
+            let best = tokens
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    self.ranks
+                        .get(&(pair[0], pair[1]))
+                        .map(|(rank, merged)| (*rank, i, *merged))
+                })
+                .min();
+
+            match best {
+                None => break,
+                Some((_, i, merged)) => {
+                    tokens[i] = merged;
+                    tokens.remove(i + 1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RankedMerges;
+    use crate::Merge;
+
+    #[test]
+    fn lowest_rank_first() {
+        // a = 0, b = 1, c = 2, bc = 3, ab = 4
+        let merges = [
+            Merge {
+                left: 1,
+                right: 2,
+                token_value: 3,
+            },
+            Merge {
+                left: 0,
+                right: 1,
+                token_value: 4,
+            },
+        ];
+        let ranked = RankedMerges::new(&merges);
+
+        let mut tokens = vec![0, 1, 2];
+        ranked.apply(&mut tokens);
+        assert_eq!(tokens, vec![0, 3]);
+
+        let mut tokens = vec![0, 1, 0, 1];
+        ranked.apply(&mut tokens);
+        assert_eq!(tokens, vec![4, 4]);
+    }
+}
@@ -0,0 +1,584 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{error::Error, ranked::RankedMerges, Tokenizer};
+
+/// SentencePiece writes spaces as this character
+const SPACE: char = '\u{2581}';
+const UNKNOWN_PIECE: &str = "<unk>";
+/// what SentencePiece decodes "<unk>" to
+const UNKNOWN_SURFACE: &str = " \u{2047} ";
+
+// SentencePiece.Type
+const TYPE_NORMAL: u64 = 1;
+const TYPE_UNKNOWN: u64 = 2;
+const TYPE_CONTROL: u64 = 3;
+const TYPE_USER_DEFINED: u64 = 4;
+const TYPE_UNUSED: u64 = 5;
+const TYPE_BYTE: u64 = 6;
+
+// TrainerSpec.ModelType
+const MODEL_TYPE_UNIGRAM: u64 = 1;
+const MODEL_TYPE_BPE: u64 = 2;
+
+/// NormalizerSpec options applied to text before encoding. Normalization
+/// rules (the precompiled character maps of nmt_nfkc and the like) are not
+/// supported, models must be trained with --normalization_rule_name=identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Normalizer {
+    /// prepend a space so the first word is encoded like the others
+    pub add_dummy_prefix: bool,
+    /// drop leading and trailing spaces, merge runs of spaces into one
+    pub remove_extra_whitespaces: bool,
+    /// pieces write spaces as U+2581
+    pub escape_whitespaces: bool,
+}
+
+/// SentencePiece defaults
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer {
+            add_dummy_prefix: true,
+            remove_extra_whitespaces: true,
+            escape_whitespaces: true,
+        }
+    }
+}
+
+impl Normalizer {
+    pub fn normalize(&self, text: &str) -> String {
+        let mut normalized = if self.remove_extra_whitespaces {
+            text.split(' ')
+                .filter(|word| !word.is_empty())
+                .collect::<Vec<&str>>()
+                .join(" ")
+        } else {
+            text.to_owned()
+        };
+        if self.add_dummy_prefix && !normalized.is_empty() {
+            normalized.insert(0, ' ');
+        }
+        normalized
+    }
+}
+
+/// Tokenizer<char> as a SentencePiece BPE model. Pieces are the vocabulary in
+/// token value order, scores rank the merges (higher merges first) and
+/// special tokens are control pieces. Byte pieces ("<0x41>") are kept apart
+/// in byte_pieces, with byte_fallback set characters missing from the
+/// vocabulary are encoded as their UTF-8 bytes.
+///
+/// encode and decode follow SentencePiece. The tokenizer holds spaces as ' '
+/// and is not normalized, Tokenizer::tokenize takes the longest token and
+/// can disagree with encode.
+#[derive(Debug)]
+pub struct SentencePieceBpe {
+    pub tokenizer: Tokenizer<char>,
+    pub scores: HashMap<usize, f32>,
+    pub normalizer: Normalizer,
+    pub byte_fallback: bool,
+    pub byte_pieces: HashMap<u8, usize>,
+    ranked: RankedMerges,
+    base: HashMap<char, usize>,
+}
+
+impl SentencePieceBpe {
+    /// Scores come from token values, earlier tokens were merged first. Text
+    /// is encoded as it is, without dummy prefix or whitespace cleanup.
+    pub fn from_tokenizer(tokenizer: Tokenizer<char>) -> Self {
+        let scores = tokenizer
+            .lookup
+            .keys()
+            .map(|token_value| (*token_value, -(*token_value as f32)))
+            .collect();
+        let normalizer = Normalizer {
+            add_dummy_prefix: false,
+            remove_extra_whitespaces: false,
+            escape_whitespaces: true,
+        };
+        SentencePieceBpe::new(tokenizer, scores, normalizer, HashMap::new())
+    }
+
+    fn new(
+        tokenizer: Tokenizer<char>,
+        scores: HashMap<usize, f32>,
+        normalizer: Normalizer,
+        byte_pieces: HashMap<u8, usize>,
+    ) -> Self {
+        let mut by_score: Vec<usize> = tokenizer.lookup.keys().copied().collect();
+        by_score.sort_unstable_by(|a, b| scores[b].total_cmp(&scores[a]).then(a.cmp(b)));
+        let ranks: HashMap<usize, usize> = by_score
+            .into_iter()
+            .enumerate()
+            .map(|(rank, token_value)| (token_value, rank))
+            .collect();
+
+        let base = tokenizer
+            .lookup
+            .iter()
+            .filter(|(_, token)| token.len() == 1)
+            .map(|(token_value, token)| (token[0], *token_value))
+            .collect();
+
+        SentencePieceBpe {
+            ranked: RankedMerges::from_splits(&tokenizer.lookup, |token_value| ranks[&token_value]),
+            tokenizer,
+            scores,
+            normalizer,
+            byte_fallback: !byte_pieces.is_empty(),
+            byte_pieces,
+            base,
+        }
+    }
+
+    /// Serialize as a ModelProto. Token values must have no gaps since piece
+    /// ids are positions, an "<unk>" piece is appended if there is none.
+    pub fn to_model(&self) -> Result<Vec<u8>, Error> {
+        let mut pieces: Vec<(usize, String, f32, u64)> = vec![];
+        let escape = self.normalizer.escape_whitespaces;
+        for (token_value, token) in &self.tokenizer.lookup {
+            let piece = token
+                .iter()
+                .map(|c| if escape && *c == ' ' { SPACE } else { *c })
+                .collect();
+            let score = self.scores.get(token_value).copied().unwrap_or(0.0);
+            pieces.push((*token_value, piece, score, TYPE_NORMAL));
+        }
+        for (name, token_value) in &self.tokenizer.special_tokens {
+            let piece_type = if name == UNKNOWN_PIECE {
+                TYPE_UNKNOWN
+            } else {
+                TYPE_CONTROL
+            };
+            pieces.push((*token_value, name.to_owned(), 0.0, piece_type));
+        }
+        for (byte, token_value) in &self.byte_pieces {
+            pieces.push((*token_value, format!("<0x{:02X}>", byte), 0.0, TYPE_BYTE));
+        }
+        pieces.sort_unstable_by_key(|(token_value, ..)| *token_value);
+
+        if let Some((position, (token_value, ..))) = pieces
+            .iter()
+            .enumerate()
+            .find(|(position, (token_value, ..))| position != token_value)
+        {
+            return Err(Error::Format(format!(
+                "token value {} is not contiguous, expected {}",
+                token_value, position
+            )));
+        }
+
+        let unk_id = match self.tokenizer.special_token(UNKNOWN_PIECE) {
+            Some(token_value) => token_value,
+            None => {
+                pieces.push((pieces.len(), UNKNOWN_PIECE.to_string(), 0.0, TYPE_UNKNOWN));
+                pieces.len() - 1
+            }
+        };
+
+        let mut model = vec![];
+        for (_, piece, score, piece_type) in &pieces {
+            let mut message = vec![];
+            write_bytes(&mut message, 1, piece.as_bytes());
+            write_tag(&mut message, 2, 5);
+            message.extend_from_slice(&score.to_le_bytes());
+            write_varint_field(&mut message, 3, *piece_type);
+            write_bytes(&mut model, 1, &message);
+        }
+
+        let mut trainer_spec = vec![];
+        write_varint_field(&mut trainer_spec, 3, MODEL_TYPE_BPE);
+        write_varint_field(&mut trainer_spec, 35, self.byte_fallback as u64);
+        write_varint_field(&mut trainer_spec, 40, unk_id as u64);
+        // int32 -1 is sign extended to 64 bits
+        for field in [41, 42, 43] {
+            write_varint_field(&mut trainer_spec, field, u64::MAX);
+        }
+        write_bytes(&mut model, 2, &trainer_spec);
+
+        let mut normalizer_spec = vec![];
+        write_bytes(&mut normalizer_spec, 1, b"identity");
+        write_varint_field(
+            &mut normalizer_spec,
+            3,
+            self.normalizer.add_dummy_prefix as u64,
+        );
+        write_varint_field(
+            &mut normalizer_spec,
+            4,
+            self.normalizer.remove_extra_whitespaces as u64,
+        );
+        write_varint_field(
+            &mut normalizer_spec,
+            5,
+            self.normalizer.escape_whitespaces as u64,
+        );
+        write_bytes(&mut model, 3, &normalizer_spec);
+
+        Ok(model)
+    }
+
+    /// Read a BPE ModelProto. Unknown and control pieces become special
+    /// tokens, unused pieces are dropped. Normalization rules and whitespace
+    /// as suffix are refused.
+    pub fn from_model(model: &[u8]) -> Result<Self, Error> {
+        let mut pieces: Vec<(String, f32, u64)> = vec![];
+        let mut model_type = MODEL_TYPE_UNIGRAM;
+        let mut byte_fallback = false;
+        let mut normalizer = Normalizer::default();
+
+        for (field, value) in read_fields(model)? {
+            match (field, value) {
+                (1, Value::Bytes(message)) => {
+                    let mut piece = String::new();
+                    let mut score = 0.0;
+                    let mut piece_type = TYPE_NORMAL;
+
+                    for (field, value) in read_fields(message)? {
+                        match (field, value) {
+                            (1, Value::Bytes(bytes)) => {
+                                piece = String::from_utf8(bytes.to_vec())
+                                    .map_err(|_| Error::Format("piece is not utf-8".to_string()))?
+                            }
+                            (2, Value::Fixed32(bits)) => score = f32::from_bits(bits),
+                            (3, Value::Varint(value)) => piece_type = value,
+                            _ => (),
+                        }
+                    }
+                    pieces.push((piece, score, piece_type));
+                }
+                (2, Value::Bytes(trainer_spec)) => {
+                    for (field, value) in read_fields(trainer_spec)? {
+                        match (field, value) {
+                            (3, Value::Varint(value)) => model_type = value,
+                            (24, Value::Varint(value)) if value != 0 => {
+                                return Err(Error::Format(
+                                    "whitespace as suffix is not supported".to_string(),
+                                ))
+                            }
+                            (35, Value::Varint(value)) => byte_fallback = value != 0,
+                            _ => (),
+                        }
+                    }
+                }
+                (3, Value::Bytes(normalizer_spec)) => {
+                    let mut name = String::new();
+                    for (field, value) in read_fields(normalizer_spec)? {
+                        match (field, value) {
+                            (1, Value::Bytes(bytes)) => {
+                                name = String::from_utf8_lossy(bytes).into_owned()
+                            }
+                            (2 | 6, Value::Bytes(rules)) if !rules.is_empty() => {
+                                return Err(Error::Format(format!(
+                                    "normalization rule {:?} is not supported, only identity",
+                                    name
+                                )))
+                            }
+                            (3, Value::Varint(value)) => normalizer.add_dummy_prefix = value != 0,
+                            (4, Value::Varint(value)) => {
+                                normalizer.remove_extra_whitespaces = value != 0
+                            }
+                            (5, Value::Varint(value)) => normalizer.escape_whitespaces = value != 0,
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if model_type != MODEL_TYPE_BPE {
+            return Err(Error::Format(format!(
+                "model type {} is not BPE",
+                model_type
+            )));
+        }
+
+        let mut tokenizer = Tokenizer::default();
+        let mut scores = HashMap::new();
+        let mut byte_pieces = HashMap::new();
+        for (token_value, (piece, score, piece_type)) in pieces.into_iter().enumerate() {
+            match piece_type {
+                TYPE_NORMAL | TYPE_USER_DEFINED => {
+                    let token: Vec<char> = piece
+                        .chars()
+                        .map(|c| match normalizer.escape_whitespaces && c == SPACE {
+                            true => ' ',
+                            false => c,
+                        })
+                        .collect();
+                    if token.is_empty() {
+                        return Err(Error::Format(format!("piece {} is empty", token_value)));
+                    }
+                    tokenizer.register(&token, token_value);
+                    scores.insert(token_value, score);
+                }
+                TYPE_UNKNOWN | TYPE_CONTROL => {
+                    tokenizer.special_tokens.insert(piece, token_value);
+                }
+                TYPE_BYTE => {
+                    let byte = piece
+                        .strip_prefix("<0x")
+                        .and_then(|hex| hex.strip_suffix('>'))
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        .ok_or_else(|| Error::Format(format!("invalid byte piece {:?}", piece)))?;
+                    byte_pieces.insert(byte, token_value);
+                }
+                TYPE_UNUSED => (),
+                _ => return Err(Error::Format(format!("unknown piece type {}", piece_type))),
+            }
+        }
+
+        let mut model = SentencePieceBpe::new(tokenizer, scores, normalizer, byte_pieces);
+        model.byte_fallback = byte_fallback;
+        Ok(model)
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        SentencePieceBpe::from_model(&fs::read(path)?)
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_model()?)?;
+        Ok(())
+    }
+
+    /// Normalize, then merge the pair with the highest score first.
+    /// Characters missing from the vocabulary become byte pieces with
+    /// byte_fallback, "<unk>" otherwise if the model has one.
+    pub fn encode(&self, text: &str) -> Vec<usize> {
+        let unknown = self.tokenizer.special_token(UNKNOWN_PIECE);
+
+        let mut tokens = vec![];
+        // no piece holds a missing character, merges stay between them
+        let mut known: Vec<usize> = vec![];
+        for c in self.normalizer.normalize(text).chars() {
+            if let Some(token_value) = self.base.get(&c) {
+                known.push(*token_value);
+                continue;
+            }
+            self.ranked.apply(&mut known);
+            tokens.append(&mut known);
+
+            let mut utf8 = [0; 4];
+            let bytes: Option<Vec<usize>> = match self.byte_fallback {
+                true => c
+                    .encode_utf8(&mut utf8)
+                    .bytes()
+                    .map(|byte| self.byte_pieces.get(&byte).copied())
+                    .collect(),
+                false => None,
+            };
+            match (bytes, unknown) {
+                (Some(bytes), _) => tokens.extend(bytes),
+                (None, Some(unknown)) => tokens.push(unknown),
+                (None, None) => panic!("no child in tokenizer that matches"),
+            }
+        }
+        self.ranked.apply(&mut known);
+        tokens.append(&mut known);
+
+        tokens
+    }
+
+    /// Byte pieces are joined back into UTF-8, "<unk>" becomes " \u{2047} ",
+    /// control pieces are dropped and so is the dummy prefix
+    pub fn decode(&self, tokens: &[usize]) -> String {
+        let bytes_by_value: HashMap<usize, u8> = self
+            .byte_pieces
+            .iter()
+            .map(|(byte, token_value)| (*token_value, *byte))
+            .collect();
+        let unknown = self.tokenizer.special_token(UNKNOWN_PIECE);
+
+        let mut bytes: Vec<u8> = vec![];
+        for token_value in tokens {
+            if let Some(token) = self.tokenizer.lookup.get(token_value) {
+                bytes.extend(token.iter().collect::<String>().bytes());
+            } else if let Some(byte) = bytes_by_value.get(token_value) {
+                bytes.push(*byte);
+            } else if unknown == Some(*token_value) {
+                bytes.extend(UNKNOWN_SURFACE.bytes());
+            }
+        }
+
+        let text = String::from_utf8_lossy(&bytes);
+        match self.normalizer.add_dummy_prefix {
+            true => text.strip_prefix(' ').unwrap_or(&text).to_owned(),
+            false => text.into_owned(),
+        }
+    }
+}
+
+enum Value<'a> {
+    Varint(u64),
+    Fixed32(u32),
+    /// no field read here uses it, the value is skipped
+    Fixed64,
+    Bytes(&'a [u8]),
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn write_tag(buffer: &mut Vec<u8>, field: u64, wire_type: u64) {
+    write_varint(buffer, (field << 3) | wire_type);
+}
+
+fn write_varint_field(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    write_tag(buffer, field, 0);
+    write_varint(buffer, value);
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    write_tag(buffer, field, 2);
+    write_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn read_varint(bytes: &[u8], pointer: &mut usize) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes
+            .get(*pointer)
+            .ok_or_else(|| Error::Format("truncated varint".to_string()))?;
+        *pointer += 1;
+
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    Err(Error::Format("varint is too long".to_string()))
+}
+
+fn take<'a>(bytes: &'a [u8], pointer: &mut usize, len: usize) -> Result<&'a [u8], Error> {
+    let end = pointer
+        .checked_add(len)
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(|| Error::Format("truncated field".to_string()))?;
+    let value = &bytes[*pointer..end];
+    *pointer = end;
+    Ok(value)
+}
+
+/// Top level fields of a protobuf message
+fn read_fields(bytes: &[u8]) -> Result<Vec<(u64, Value<'_>)>, Error> {
+    let mut fields = vec![];
+    let mut pointer = 0;
+
+    while pointer < bytes.len() {
+        let tag = read_varint(bytes, &mut pointer)?;
+        let value = match tag & 7 {
+            0 => Value::Varint(read_varint(bytes, &mut pointer)?),
+            1 => {
+                take(bytes, &mut pointer, 8)?;
+                Value::Fixed64
+            }
+            2 => {
+                let len = read_varint(bytes, &mut pointer)? as usize;
+                Value::Bytes(take(bytes, &mut pointer, len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(
+                take(bytes, &mut pointer, 4)?.try_into().unwrap(),
+            )),
+            wire_type => {
+                return Err(Error::Format(format!(
+                    "unsupported wire type {}",
+                    wire_type
+                )))
+            }
+        };
+        fields.push((tag >> 3, value));
+    }
+
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_bytes, write_varint_field, Normalizer, SentencePieceBpe};
+    use crate::{generate, test_data::RAW_TEXT};
+
+    /// laid out the way spm_train writes --model_type=bpe --byte_fallback
+    /// --normalization_rule_name=identity models: <unk> <s> </s>, the 256 byte
+    /// pieces, merges scored 0, -1, ... then the characters. Assembled by hand
+    /// as sentencepiece is not a dependency here.
+    const SMALL_BPE: &[u8] = include_bytes!("../fixtures/small_bpe.model");
+
+    #[test]
+    fn sentencepiece_fixture() {
+        let model = SentencePieceBpe::from_model(SMALL_BPE).unwrap();
+        assert_eq!(model.normalizer, Normalizer::default());
+        assert!(model.byte_fallback);
+        assert_eq!(model.byte_pieces.len(), 256);
+        assert_eq!(model.tokenizer.special_token("</s>"), Some(2));
+
+        // ▁hello ▁w or l d
+        let hello_world = vec![263, 265, 264, 267, 273];
+        assert_eq!(model.encode("hello world"), hello_world);
+        assert_eq!(model.encode("  hello   world "), hello_world);
+        assert_eq!(model.decode(&hello_world), "hello world");
+
+        // i and ! are not pieces, <0x69> <0x21>
+        let fallback = model.encode("hi!");
+        assert_eq!(fallback, vec![266, 270, 3 + 0x69, 3 + 0x21]);
+        assert_eq!(model.decode(&fallback), "hi!");
+        assert_eq!(model.decode(&model.encode("h\u{e9}")), "h\u{e9}");
+
+        let loaded = SentencePieceBpe::from_model(&model.to_model().unwrap()).unwrap();
+        assert_eq!(loaded.normalizer, model.normalizer);
+        assert_eq!(loaded.byte_pieces, model.byte_pieces);
+        assert_eq!(
+            loaded.encode("hi there world"),
+            model.encode("hi there world")
+        );
+    }
+
+    #[test]
+    fn sentencepiece_round_trip() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 96);
+        let eos = tokenizer.add_special_token("<eos>");
+
+        let model = SentencePieceBpe::from_tokenizer(tokenizer);
+        let bytes = model.to_model().unwrap();
+        let loaded = SentencePieceBpe::from_model(&bytes).unwrap();
+
+        assert_eq!(model.tokenizer.lookup, loaded.tokenizer.lookup);
+        assert_eq!(model.scores, loaded.scores);
+        assert_eq!(loaded.tokenizer.special_token("<eos>"), Some(eos));
+        assert_eq!(loaded.tokenizer.special_token("<unk>"), Some(eos + 1));
+
+        let text: String = text_val.iter().collect();
+        assert_eq!(model.encode(&text), loaded.encode(&text));
+        // unknown characters fall back to <unk>
+        assert_eq!(loaded.encode("\u{1F3B5}"), vec![eos + 1]);
+    }
+
+    #[test]
+    fn sentencepiece_rejects_unigram() {
+        let mut piece = vec![];
+        write_bytes(&mut piece, 1, "a".as_bytes());
+        let mut trainer_spec = vec![];
+        write_varint_field(&mut trainer_spec, 3, 1);
+
+        let mut bytes = vec![];
+        write_bytes(&mut bytes, 1, &piece);
+        write_bytes(&mut bytes, 2, &trainer_spec);
+
+        assert!(SentencePieceBpe::from_model(&bytes).is_err());
+
+        // nmt_nfkc and other rules ship a precompiled character map
+        let mut normalizer_spec = vec![];
+        write_bytes(&mut normalizer_spec, 1, b"nmt_nfkc");
+        write_bytes(&mut normalizer_spec, 2, &[1, 2, 3]);
+        let mut bytes = SMALL_BPE.to_vec();
+        write_bytes(&mut bytes, 3, &normalizer_spec);
+        assert!(SentencePieceBpe::from_model(&bytes).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::{fmt::Debug, hash::Hash};
+
+use crate::{Node, Tokenizer};
+
+/// Tokenize input that arrives in chunks. The trie node reached by the
+/// current partial token is kept between calls to push, so a token can span
+/// chunk boundaries and the output is the same as tokenizing everything at once.
+pub struct StreamingEncoder<'a, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    tokenizer: &'a Tokenizer<T>,
+    current: Option<&'a Node<T>>,
+    /// elements matched since the start of the partial token
+    pending: Vec<T>,
+    /// value and length of the longest token in pending, the path can go on
+    /// through nodes that are not tokens
+    longest: Option<(usize, usize)>,
+}
+
+impl<'a, T> StreamingEncoder<'a, T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    pub fn new(tokenizer: &'a Tokenizer<T>) -> Self {
+        StreamingEncoder {
+            tokenizer,
+            current: None,
+            pending: vec![],
+            longest: None,
+        }
+    }
+
+    /// Feed a chunk, writing out every token that can no longer be extended
+    pub fn push(&mut self, chunk: &[T], write_buffer: &mut Vec<usize>) {
+        for elem in chunk {
+            self.feed(elem, write_buffer);
+        }
+
+        // a leaf cannot grow with the next chunk, no need to hold it
+        while self.current.is_some_and(|node| node.children.is_empty()) {
+            self.flush(write_buffer);
+        }
+    }
+
+    /// True if part of a token is held back waiting for more input
+    pub fn has_pending(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// End of input, write out the pending tokens if any
+    pub fn finish(mut self, write_buffer: &mut Vec<usize>) {
+        while self.current.is_some() {
+            self.flush(write_buffer);
+        }
+    }
+
+    fn feed(&mut self, elem: &T, write_buffer: &mut Vec<usize>) {
+        if let Some(child) = self.current.and_then(|node| node.children.get(elem)) {
+            self.advance(child, elem);
+            return;
+        }
+
+        // stack of elements to match, the ones after an emitted token go
+        // back on it
+        let mut replay = vec![elem.to_owned()];
+        while let Some(elem) = replay.pop() {
+            if self.current.is_some() {
+                match self.current.and_then(|node| node.children.get(&elem)) {
+                    Some(child) => self.advance(child, &elem),
+                    None => {
+                        replay.push(elem);
+                        replay.extend(self.emit(write_buffer).into_iter().rev());
+                    }
+                }
+                continue;
+            }
+
+            match self.tokenizer.children.get(&elem) {
+                None => panic!("no child in tokenizer that matches"),
+                Some(child) => self.advance(child, &elem),
+            }
+        }
+    }
+
+    fn advance(&mut self, node: &'a Node<T>, elem: &T) {
+        self.current = Some(node);
+        self.pending.push(elem.to_owned());
+        if self.tokenizer.ends_token(node) {
+            self.longest = Some((node.token_value, self.pending.len()));
+        }
+    }
+
+    /// Write the longest pending token, return the elements held after it
+    fn emit(&mut self, write_buffer: &mut Vec<usize>) -> Vec<T> {
+        let (token_value, len) = match self.longest.take() {
+            None => panic!("no token starts with {:?}", self.pending[0]),
+            Some(longest) => longest,
+        };
+        write_buffer.push(token_value);
+
+        let rest = self.pending.split_off(len);
+        self.pending.clear();
+        self.current = None;
+        rest
+    }
+
+    /// emit, then match the elements after the token again
+    fn flush(&mut self, write_buffer: &mut Vec<usize>) {
+        for elem in self.emit(write_buffer) {
+            self.feed(&elem, write_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamingEncoder;
+    use crate::{generate, test_data::RAW_TEXT};
+
+    #[test]
+    fn chunked_matches_buffered_tokenize() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let tokenizer = generate(&text_val, 128);
+
+        let mut expected: Vec<usize> = vec![];
+        tokenizer.tokenize(&text_val, &mut expected, &mut 0);
+
+        for chunk_size in [1, 2, 3, 7, 64, 5000] {
+            let mut encoder = StreamingEncoder::new(&tokenizer);
+            let mut token_buffer: Vec<usize> = vec![];
+            for chunk in text_val.chunks(chunk_size) {
+                encoder.push(chunk, &mut token_buffer);
+            }
+            encoder.finish(&mut token_buffer);
+
+            assert_eq!(expected, token_buffer, "chunk size {}", chunk_size);
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// File or directory in the temp directory, removed on drop so failing
+/// tests do not leave it behind
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        TempPath(std::env::temp_dir().join(format!("{}_{}", name, std::process::id())))
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // may not have been created
+        let _ = match self.0.is_dir() {
+            true => fs::remove_dir_all(&self.0),
+            false => fs::remove_file(&self.0),
+        };
+    }
+}
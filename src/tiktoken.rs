@@ -0,0 +1,153 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{error::Error, ranked::RankedMerges, Tokenizer};
+
+/// Byte level BPE in the tiktoken `.tiktoken` format, one base64 token and
+/// its rank per line. Ranks are the token values, and encoding merges the
+/// adjacent pair whose concatenation has the lowest rank, as tiktoken does.
+/// The ranks are not prefix-closed, tokenizer takes the longest token that
+/// is in the file and can disagree with encode.
+#[derive(Debug)]
+pub struct TiktokenBpe {
+    pub tokenizer: Tokenizer<u8>,
+    ranked: RankedMerges,
+    reverse: HashMap<Vec<u8>, usize>,
+}
+
+impl TiktokenBpe {
+    /// Token values of the tokenizer are used as ranks
+    pub fn from_tokenizer(tokenizer: Tokenizer<u8>) -> Self {
+        let reverse: HashMap<Vec<u8>, usize> = tokenizer
+            .lookup
+            .iter()
+            .map(|(token_value, token)| (token.to_owned(), *token_value))
+            .collect();
+
+        let ranked = RankedMerges::from_splits(&tokenizer.lookup, |token_value| token_value);
+
+        TiktokenBpe {
+            tokenizer,
+            ranked,
+            reverse,
+        }
+    }
+
+    pub fn load(contents: &str) -> Result<Self, Error> {
+        let mut tokenizer = Tokenizer::default();
+
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            let (token, rank) = line
+                .split_once(' ')
+                .ok_or_else(|| Error::Format(format!("invalid line {:?}", line)))?;
+            let token = STANDARD
+                .decode(token)
+                .map_err(|_| Error::Format(format!("invalid base64 token {:?}", token)))?;
+            let rank: usize = rank
+                .parse()
+                .map_err(|_| Error::Format(format!("invalid rank {:?}", rank)))?;
+
+            if token.is_empty() {
+                return Err(Error::Format(format!("token {} is empty", rank)));
+            }
+            if tokenizer.lookup.contains_key(&rank) {
+                return Err(Error::Format(format!("rank {} is used twice", rank)));
+            }
+            tokenizer.register(&token, rank);
+        }
+
+        Ok(TiktokenBpe::from_tokenizer(tokenizer))
+    }
+
+    /// Lines ordered by rank, special tokens are not part of the format
+    pub fn save(&self) -> String {
+        let mut token_values: Vec<&usize> = self.tokenizer.lookup.keys().collect();
+        token_values.sort_unstable();
+
+        let mut contents = String::new();
+        for token_value in token_values {
+            let token = STANDARD.encode(&self.tokenizer.lookup[token_value]);
+            contents.push_str(&format!("{} {}\n", token, token_value));
+        }
+        contents
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        TiktokenBpe::load(&fs::read_to_string(path)?)
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.save())?;
+        Ok(())
+    }
+
+    /// Encode a single piece. tiktoken first splits text with the regex of
+    /// its encoding, byte_level::pretokenize gives the GPT-2 (r50k) split.
+    pub fn encode(&self, piece: &[u8]) -> Vec<usize> {
+        if let Some(token_value) = self.reverse.get(piece) {
+            return vec![*token_value];
+        }
+
+        let mut tokens: Vec<usize> = piece
+            .iter()
+            .map(|byte| match self.reverse.get(&[*byte][..]) {
+                None => panic!("no child in tokenizer that matches"),
+                Some(token_value) => *token_value,
+            })
+            .collect();
+        self.ranked.apply(&mut tokens);
+        tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TiktokenBpe;
+
+    const FIXTURE: &str = include_str!("../fixtures/small.tiktoken");
+
+    #[test]
+    fn tiktoken_fixture() {
+        let model = TiktokenBpe::load(FIXTURE).unwrap();
+
+        // ids from the tiktoken merge algorithm, "cabcd" shows where it
+        // differs from the longest match of Tokenizer::tokenize
+        let cases: [(&str, Vec<usize>); 7] = [
+            ("abcd", vec![8]),
+            ("abcdab", vec![8, 5]),
+            ("bcd", vec![9]),
+            ("cabcd", vec![2, 8]),
+            ("dabc a", vec![11, 2, 10]),
+            ("cab abcd", vec![13, 4, 8]),
+            ("bcab", vec![7, 5]),
+        ];
+        for (piece, expected) in cases {
+            assert_eq!(model.encode(piece.as_bytes()), expected, "{}", piece);
+        }
+
+        assert_eq!(model.save(), FIXTURE);
+    }
+
+    #[test]
+    fn tokenize_falls_back_to_tokens() {
+        let model = TiktokenBpe::load(FIXTURE).unwrap();
+
+        // "abc" and "cabc" are on trie paths but not tokens, "a" has value 0
+        let cases: [(&str, Vec<usize>); 5] = [
+            ("abc", vec![5, 2]),
+            ("cabc", vec![13, 2]),
+            ("abca", vec![5, 12]),
+            ("bca", vec![7, 0]),
+            ("abcab", vec![5, 13]),
+        ];
+        for (piece, expected) in cases {
+            let mut token_buffer: Vec<usize> = vec![];
+            model
+                .tokenizer
+                .tokenize(piece.as_bytes(), &mut token_buffer, &mut 0);
+            assert_eq!(token_buffer, expected, "{}", piece);
+            assert_eq!(model.encode(piece.as_bytes()), expected, "{}", piece);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::{fmt::Debug, hash::Hash};
+
+use crate::{error::Error, Tokenizer};
+
+/// Special token naming the track of the tokens that follow
+pub fn track_token_name(track: usize) -> String {
+    format!("<track_{}>", track)
+}
+
+/// Interleaves independent tracks (channels) into one token stream. Tracks
+/// take turns in a fixed order, each turn is the track's special token
+/// followed by the tokens of its next segment, so tokens never span two
+/// tracks and the stream can be split back after decoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tracks {
+    track_tokens: Vec<usize>,
+}
+
+impl Tracks {
+    /// Register the special tokens of track_count tracks, reusing any the
+    /// tokenizer already has
+    pub fn new<T>(tokenizer: &mut Tokenizer<T>, track_count: usize) -> Self
+    where
+        T: Eq + Hash + Clone + Debug,
+    {
+        Tracks {
+            track_tokens: (0..track_count)
+                .map(|track| tokenizer.add_special_token(&track_token_name(track)))
+                .collect(),
+        }
+    }
+
+    /// Track special tokens of a trained tokenizer, without adding any
+    pub fn from_tokenizer<T>(tokenizer: &Tokenizer<T>, track_count: usize) -> Result<Self, Error>
+    where
+        T: Eq + Hash + Clone + Debug,
+    {
+        let track_tokens = (0..track_count)
+            .map(|track| {
+                let name = track_token_name(track);
+                tokenizer
+                    .special_token(&name)
+                    .ok_or_else(|| Error::Format(format!("missing special token {}", name)))
+            })
+            .collect::<Result<Vec<usize>, Error>>()?;
+        Ok(Tracks { track_tokens })
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.track_tokens.len()
+    }
+
+    pub fn track_token(&self, track: usize) -> usize {
+        self.track_tokens[track]
+    }
+
+    /// Tokenize segment_length elements of each track in turn until every
+    /// track is consumed. Finished tracks are skipped.
+    pub fn interleave<T>(
+        &self,
+        tokenizer: &Tokenizer<T>,
+        tracks: &[&[T]],
+        segment_length: usize,
+        write_buffer: &mut Vec<usize>,
+    ) where
+        T: Eq + Hash + Clone + Debug,
+    {
+        assert!(
+            tracks.len() <= self.track_count(),
+            "{} tracks for {} track tokens",
+            tracks.len(),
+            self.track_count()
+        );
+        assert!(segment_length > 0, "segment length must be above 0");
+
+        let mut start = 0;
+        while tracks.iter().any(|track| start < track.len()) {
+            for (track, elements) in tracks.iter().enumerate() {
+                if start >= elements.len() {
+                    continue;
+                }
+                let end = elements.len().min(start + segment_length);
+
+                write_buffer.push(self.track_tokens[track]);
+                tokenizer.tokenize(&elements[start..end], write_buffer, &mut 0);
+            }
+            start += segment_length;
+        }
+    }
+
+    /// Split a decoded stream back into tracks, in track order
+    pub fn deinterleave<T>(
+        &self,
+        tokenizer: &Tokenizer<T>,
+        read_buffer: &[usize],
+    ) -> Result<Vec<Vec<T>>, Error>
+    where
+        T: Eq + Hash + Clone + Debug,
+    {
+        let mut tracks: Vec<Vec<T>> = vec![vec![]; self.track_count()];
+        let mut current = None;
+
+        for token_value in read_buffer {
+            if let Some(track) = self
+                .track_tokens
+                .iter()
+                .position(|value| value == token_value)
+            {
+                current = Some(track);
+                continue;
+            }
+
+            let track = current.ok_or_else(|| {
+                Error::Format(format!("token {} before any track token", token_value))
+            })?;
+            match tokenizer.lookup.get(token_value) {
+                Some(token) => tracks[track].extend_from_slice(token),
+                // other special tokens carry no elements
+                None if tokenizer.is_special_token(*token_value) => (),
+                None => {
+                    return Err(Error::Format(format!(
+                        "unknown token value {}",
+                        token_value
+                    )))
+                }
+            }
+        }
+
+        Ok(tracks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tracks;
+    use crate::{generate, test_data::RAW_TEXT};
+
+    #[test]
+    fn interleave_round_trip() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 96);
+        let tracks = Tracks::new(&mut tokenizer, 3);
+        assert_eq!(Tracks::from_tokenizer(&tokenizer, 3).unwrap(), tracks);
+
+        let inputs: Vec<&[char]> = vec![&text_val[..700], &text_val[700..760], &text_val[760..]];
+        let mut token_buffer: Vec<usize> = vec![];
+        tracks.interleave(&tokenizer, &inputs, 64, &mut token_buffer);
+
+        // the second track is done after its first turn
+        let turns: Vec<usize> = token_buffer
+            .iter()
+            .filter_map(|token_value| {
+                (0..3).find(|track| tracks.track_token(*track) == *token_value)
+            })
+            .collect();
+        assert_eq!(turns[..6], [0, 1, 2, 0, 2, 0]);
+
+        let decoded = tracks.deinterleave(&tokenizer, &token_buffer).unwrap();
+        assert_eq!(decoded, inputs);
+
+        // everything must belong to a track
+        assert!(tracks.deinterleave(&tokenizer, &token_buffer[1..]).is_err());
+    }
+}
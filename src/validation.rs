@@ -0,0 +1,401 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug},
+    hash::Hash,
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{error::Error, Node, Tokenizer};
+
+/// Something a loaded tokenizer gets wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Issue<T> {
+    EmptyToken {
+        token_value: usize,
+    },
+    /// lookup has the token but no trie path spells it
+    MissingFromTrie {
+        token_value: usize,
+    },
+    /// the trie path of a token ends on another value
+    TrieMismatch {
+        token_value: usize,
+        found: usize,
+    },
+    /// a trie path carries a value that is not in lookup
+    UnknownTrieToken {
+        path: Vec<T>,
+        token_value: usize,
+    },
+    /// a trie leaf that is not a token, tokenize would emit a wrong value
+    DanglingLeaf {
+        path: Vec<T>,
+    },
+    /// a trie path carries the value of a token spelled differently
+    PathMismatch {
+        path: Vec<T>,
+        token_value: usize,
+    },
+    /// an intermediate trie node that is not a token, matching can stop
+    /// there and falls back to a shorter token. Imported vocabularies are
+    /// often like this, so it is reported but does not fail loading.
+    NonTokenPrefix {
+        path: Vec<T>,
+    },
+    /// the same elements are registered under several values
+    DuplicateToken {
+        elements: Vec<T>,
+        token_values: Vec<usize>,
+    },
+    /// a value used by more than one special token or by a special token and lookup
+    DuplicateTokenValue {
+        token_value: usize,
+    },
+    /// no token uses this value although larger values exist
+    Gap {
+        token_value: usize,
+    },
+    /// element used in tokens without a single element token, inputs
+    /// containing it alone cannot be tokenized
+    MissingBaseSymbol {
+        element: T,
+    },
+}
+
+impl<T: Debug> fmt::Display for Issue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::EmptyToken { token_value } => write!(f, "token {} is empty", token_value),
+            Issue::MissingFromTrie { token_value } => {
+                write!(f, "token {} is not in the trie", token_value)
+            }
+            Issue::TrieMismatch { token_value, found } => write!(
+                f,
+                "trie path of token {} holds value {}",
+                token_value, found
+            ),
+            Issue::UnknownTrieToken { path, token_value } => write!(
+                f,
+                "trie path {:?} holds value {} missing from lookup",
+                path, token_value
+            ),
+            Issue::DanglingLeaf { path } => write!(f, "trie leaf {:?} is not a token", path),
+            Issue::PathMismatch { path, token_value } => write!(
+                f,
+                "trie path {:?} holds value {} of another token",
+                path, token_value
+            ),
+            Issue::NonTokenPrefix { path } => {
+                write!(f, "trie prefix {:?} is not a token", path)
+            }
+            Issue::DuplicateToken {
+                elements,
+                token_values,
+            } => write!(f, "{:?} is registered as {:?}", elements, token_values),
+            Issue::DuplicateTokenValue { token_value } => {
+                write!(f, "token value {} is used twice", token_value)
+            }
+            Issue::Gap { token_value } => write!(f, "token value {} is unused", token_value),
+            Issue::MissingBaseSymbol { element } => {
+                write!(f, "element {:?} has no single element token", element)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationReport<T> {
+    pub issues: Vec<Issue<T>>,
+}
+
+impl<T> Issue<T> {
+    /// Reported without making the tokenizer invalid
+    pub fn is_informational(&self) -> bool {
+        matches!(self, Issue::NonTokenPrefix { .. })
+    }
+}
+
+impl<T> ValidationReport<T> {
+    /// No issues besides informational ones
+    pub fn is_valid(&self) -> bool {
+        self.issues.iter().all(|issue| issue.is_informational())
+    }
+}
+
+impl<T: Debug> ValidationReport<T> {
+    /// Error::Validation listing the issues that are not informational, if
+    /// there are any
+    pub fn into_result(self) -> Result<(), Error> {
+        if self.is_valid() {
+            Ok(())
+        } else {
+            let errors = ValidationReport {
+                issues: self
+                    .issues
+                    .into_iter()
+                    .filter(|issue| !issue.is_informational())
+                    .collect(),
+            };
+            Err(Error::Validation(errors.to_string()))
+        }
+    }
+}
+
+impl<T: Debug> fmt::Display for ValidationReport<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} issue(s)", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n- {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> Tokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    /// Check that trie, lookup and special tokens agree, listing every problem found
+    pub fn validate(&self) -> ValidationReport<T> {
+        let mut issues = vec![];
+
+        let mut token_values: Vec<usize> = self.lookup.keys().copied().collect();
+        token_values.sort_unstable();
+
+        // lookup against the trie
+        let mut by_elements: HashMap<&[T], Vec<usize>> = HashMap::new();
+        for token_value in &token_values {
+            let token = &self.lookup[token_value];
+            if token.is_empty() {
+                issues.push(Issue::EmptyToken {
+                    token_value: *token_value,
+                });
+                continue;
+            }
+            by_elements.entry(token).or_default().push(*token_value);
+
+            match self.find_node(token) {
+                None => issues.push(Issue::MissingFromTrie {
+                    token_value: *token_value,
+                }),
+                Some(node) if node.token_value != *token_value => {
+                    issues.push(Issue::TrieMismatch {
+                        token_value: *token_value,
+                        found: node.token_value,
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+
+        let mut duplicates: Vec<(&[T], Vec<usize>)> = by_elements
+            .iter()
+            .filter(|(_, values)| values.len() > 1)
+            .map(|(elements, values)| (*elements, values.to_owned()))
+            .collect();
+        duplicates.sort_unstable_by_key(|(_, values)| values[0]);
+        for (elements, token_values) in duplicates {
+            issues.push(Issue::DuplicateToken {
+                elements: elements.to_vec(),
+                token_values,
+            });
+        }
+
+        // trie against lookup
+        let mut stack: Vec<(Vec<T>, &Node<T>)> = self
+            .children
+            .values()
+            .map(|node| (vec![node.byte_value.to_owned()], node))
+            .collect();
+        while let Some((path, node)) = stack.pop() {
+            if !by_elements.contains_key(&path[..]) {
+                if node.children.is_empty() {
+                    issues.push(Issue::DanglingLeaf { path: path.clone() });
+                } else if node.token_value == 0 {
+                    // intermediate nodes that are not tokens hold 0
+                    issues.push(Issue::NonTokenPrefix { path: path.clone() });
+                } else if self.lookup.contains_key(&node.token_value) {
+                    issues.push(Issue::PathMismatch {
+                        path: path.clone(),
+                        token_value: node.token_value,
+                    });
+                } else {
+                    issues.push(Issue::UnknownTrieToken {
+                        path: path.clone(),
+                        token_value: node.token_value,
+                    });
+                }
+            }
+
+            for child in node.children.values() {
+                let mut child_path = path.clone();
+                child_path.push(child.byte_value.to_owned());
+                stack.push((child_path, child));
+            }
+        }
+
+        // token values
+        let mut used: HashSet<usize> = self.lookup.keys().copied().collect();
+        let mut specials: Vec<usize> = self.special_tokens.values().copied().collect();
+        specials.sort_unstable();
+        for token_value in specials {
+            if !used.insert(token_value) {
+                issues.push(Issue::DuplicateTokenValue { token_value });
+            }
+        }
+        if let Some(max) = used.iter().max() {
+            for token_value in 0..*max {
+                if !used.contains(&token_value) {
+                    issues.push(Issue::Gap { token_value });
+                }
+            }
+        }
+
+        // every element needs a token of its own
+        let mut missing: Vec<&T> = vec![];
+        let mut seen: HashSet<&T> = HashSet::new();
+        for token_value in &token_values {
+            for elem in &self.lookup[token_value] {
+                if seen.insert(elem) && !by_elements.contains_key(std::slice::from_ref(elem)) {
+                    missing.push(elem);
+                }
+            }
+        }
+        for elem in missing {
+            issues.push(Issue::MissingBaseSymbol {
+                element: elem.to_owned(),
+            });
+        }
+
+        ValidationReport { issues }
+    }
+
+    /// Parse the serde json layout and validate the result
+    pub fn from_json(json: &str) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+    {
+        let tokenizer = Self::from_json_unchecked(json)?;
+        tokenizer.validate().into_result()?;
+        Ok(tokenizer)
+    }
+
+    /// Same as from_json without Tokenizer::validate
+    pub fn from_json_unchecked(json: &str) -> Result<Self, Error>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Node at the end of the trie path spelling token
+    pub(crate) fn find_node(&self, token: &[T]) -> Option<&Node<T>> {
+        let mut node = self.children.get(token.first()?)?;
+        for elem in &token[1..] {
+            node = node.children.get(elem)?;
+        }
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Issue;
+    use crate::{error::Error, generate, test_data::RAW_TEXT, Tokenizer};
+
+    #[test]
+    fn trained_tokenizer_is_valid() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 96);
+        tokenizer.add_special_token("<eos>");
+
+        let report = tokenizer.validate();
+        assert!(report.is_valid(), "{}", report);
+    }
+
+    #[test]
+    fn reject_on_load_unless_unchecked() {
+        let json = r#"{"children": [], "lookup": [[0, ["a"]], [2, ["b"]]]}"#;
+
+        assert!(matches!(
+            Tokenizer::<char>::from_json(json),
+            Err(Error::Validation(_))
+        ));
+        let tokenizer = Tokenizer::<char>::from_json_unchecked(json).unwrap();
+        assert_eq!(
+            tokenizer.validate().issues,
+            vec![
+                Issue::MissingFromTrie { token_value: 0 },
+                Issue::MissingFromTrie { token_value: 2 },
+                Issue::Gap { token_value: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn report_every_issue() {
+        let mut tokenizer: Tokenizer<char> = Tokenizer::default();
+        tokenizer.register(&['a'], 0);
+        tokenizer.register(&['a', 'b'], 1);
+        tokenizer.register(&['a', 'b'], 4);
+        tokenizer.lookup.insert(5, vec!['z']);
+        tokenizer.special_tokens.insert("<eos>".to_string(), 5);
+
+        let issues = tokenizer.validate().issues;
+        assert!(issues.contains(&Issue::TrieMismatch {
+            token_value: 1,
+            found: 4
+        }));
+        assert!(issues.contains(&Issue::DuplicateToken {
+            elements: vec!['a', 'b'],
+            token_values: vec![1, 4]
+        }));
+        assert!(issues.contains(&Issue::MissingFromTrie { token_value: 5 }));
+        assert!(issues.contains(&Issue::DuplicateTokenValue { token_value: 5 }));
+        assert!(issues.contains(&Issue::Gap { token_value: 2 }));
+        assert!(issues.contains(&Issue::Gap { token_value: 3 }));
+        assert!(issues.contains(&Issue::MissingBaseSymbol { element: 'b' }));
+        assert_eq!(issues.len(), 7);
+    }
+
+    #[test]
+    fn report_prefixes_that_are_not_tokens() {
+        let mut tokenizer: Tokenizer<char> = Tokenizer::default();
+        tokenizer.register(&['a'], 0);
+        tokenizer.register(&['b'], 1);
+        tokenizer.register(&['a', 'b', 'a'], 2);
+
+        assert_eq!(
+            tokenizer.validate().issues,
+            vec![Issue::NonTokenPrefix {
+                path: vec!['a', 'b']
+            }]
+        );
+        // informational, loading still works
+        let json = serde_json::to_string(&tokenizer).unwrap();
+        assert!(Tokenizer::<char>::from_json(&json).is_ok());
+
+        // the node now claims the value of "b"
+        tokenizer
+            .children
+            .get_mut(&'a')
+            .unwrap()
+            .children
+            .get_mut(&'b')
+            .unwrap()
+            .token_value = 1;
+        assert_eq!(
+            tokenizer.validate().issues,
+            vec![Issue::PathMismatch {
+                path: vec!['a', 'b'],
+                token_value: 1
+            }]
+        );
+        let json = serde_json::to_string(&tokenizer).unwrap();
+        assert!(matches!(
+            Tokenizer::<char>::from_json(&json),
+            Err(Error::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,509 @@
+use std::{fs, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+const MAGIC: &[u8; 4] = b"Vgm ";
+const GD3_MAGIC: &[u8; 4] = b"Gd3 ";
+
+// header fields, offsets are relative to their own position
+const EOF_OFFSET: usize = 0x04;
+const VERSION: usize = 0x08;
+const GD3_OFFSET: usize = 0x14;
+const TOTAL_SAMPLES: usize = 0x18;
+const LOOP_OFFSET: usize = 0x1C;
+const LOOP_SAMPLES: usize = 0x20;
+const DATA_OFFSET: usize = 0x34;
+
+/// Written when the header has no version, the first one with a data offset
+const DEFAULT_VERSION: u32 = 0x150;
+
+/// Sample count of the 0x62 and 0x63 shorthand waits (1/60 s and 1/50 s at 44100 Hz)
+const WAIT_NTSC: u32 = 735;
+const WAIT_PAL: u32 = 882;
+
+/// One command of a VGM stream. Waits of every encoding become Wait, so equal
+/// pauses are the same element when used as T in Tokenizer<T>.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VgmEvent {
+    /// 0x50, SN76489 write
+    PsgWrite { value: u8 },
+    /// Register write with a one byte register and value, 0x51-0x5F (YM2413,
+    /// YM2612, YM2151...) and 0xA0-0xBF
+    Write {
+        command: u8,
+        register: u8,
+        value: u8,
+    },
+    /// Pause in samples at 44100 Hz
+    Wait(u32),
+    /// 0x8n, YM2612 DAC write from the data bank followed by a wait of n samples
+    DacWriteWait(u8),
+    /// 0x67 data block, second_chip is bit 31 of the size field
+    DataBlock {
+        block_type: u8,
+        second_chip: bool,
+        data: Vec<u8>,
+    },
+    /// 0xE0, seek in the PCM data bank
+    PcmSeek(u32),
+    /// Position the loop offset of the header points to, not a command
+    LoopStart,
+    /// 0x66
+    End,
+    /// Any other command with its raw operands
+    Other { command: u8, operands: Vec<u8> },
+}
+
+impl VgmEvent {
+    /// Samples the event waits for
+    pub fn samples(&self) -> u32 {
+        match self {
+            VgmEvent::Wait(samples) => *samples,
+            VgmEvent::DacWriteWait(samples) => *samples as u32,
+            _ => 0,
+        }
+    }
+
+    /// Append the command bytes, waits use the shortest encoding
+    pub fn write_to(&self, out: &mut Vec<u8>) {
+        match self {
+            VgmEvent::PsgWrite { value } => out.extend_from_slice(&[0x50, *value]),
+            VgmEvent::Write {
+                command,
+                register,
+                value,
+            } => out.extend_from_slice(&[*command, *register, *value]),
+            VgmEvent::Wait(samples) => write_wait(*samples, out),
+            VgmEvent::DacWriteWait(samples) => out.push(0x80 | samples),
+            VgmEvent::DataBlock {
+                block_type,
+                second_chip,
+                data,
+            } => {
+                let size = data.len() as u32 | if *second_chip { 1 << 31 } else { 0 };
+                out.extend_from_slice(&[0x67, 0x66, *block_type]);
+                out.extend_from_slice(&size.to_le_bytes());
+                out.extend_from_slice(data);
+            }
+            VgmEvent::PcmSeek(offset) => {
+                out.push(0xE0);
+                out.extend_from_slice(&offset.to_le_bytes());
+            }
+            VgmEvent::LoopStart => (),
+            VgmEvent::End => out.push(0x66),
+            VgmEvent::Other { command, operands } => {
+                out.push(*command);
+                out.extend_from_slice(operands);
+            }
+        }
+    }
+}
+
+fn write_wait(mut samples: u32, out: &mut Vec<u8>) {
+    match samples {
+        0 => (),
+        1..=16 => out.push(0x70 | (samples - 1) as u8),
+        WAIT_NTSC => out.push(0x62),
+        WAIT_PAL => out.push(0x63),
+        _ => {
+            while samples > 0 {
+                let chunk = samples.min(u16::MAX as u32);
+                out.push(0x61);
+                out.extend_from_slice(&(chunk as u16).to_le_bytes());
+                samples -= chunk;
+            }
+        }
+    }
+}
+
+/// Operand count of commands without a dedicated variant, None if unknown
+fn operand_count(command: u8) -> Option<usize> {
+    match command {
+        0x30..=0x3F | 0x4F | 0x94 => Some(1),
+        0x40..=0x4E => Some(2),
+        0x64 | 0xC0..=0xDF => Some(3),
+        0x90 | 0x91 | 0x95 | 0xE1..=0xFF => Some(4),
+        0x92 => Some(5),
+        0x93 => Some(10),
+        0x68 => Some(11),
+        _ => None,
+    }
+}
+
+/// Uncompressed VGM file (gzipped .vgz files must be inflated first). The
+/// header is kept as is, its offsets and sample counts are recomputed when
+/// writing so events can be edited or decoded from tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VgmFile {
+    /// Everything before the command stream
+    pub header: Vec<u8>,
+    pub events: Vec<VgmEvent>,
+    /// GD3 tag block, magic included
+    pub gd3: Option<Vec<u8>>,
+}
+
+impl VgmFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < 0x40 || &bytes[..4] != MAGIC {
+            return Err(Error::Format("not a vgm file".to_string()));
+        }
+
+        // before 1.50 the data offset is unused and commands start at 0x40
+        let data_start = match read_offset(bytes, DATA_OFFSET) {
+            Some(start) if read_u32(bytes, VERSION) >= 0x150 => start,
+            _ => 0x40,
+        };
+        if data_start > bytes.len() {
+            return Err(Error::Format("data offset past end of file".to_string()));
+        }
+        let loop_start = read_offset(bytes, LOOP_OFFSET);
+
+        let gd3 = match read_offset(bytes, GD3_OFFSET) {
+            Some(start) => {
+                let tag = bytes
+                    .get(start..start + 12)
+                    .filter(|tag| &tag[..4] == GD3_MAGIC)
+                    .ok_or_else(|| Error::Format("invalid gd3 offset".to_string()))?;
+                let len = 12 + read_u32(tag, 8) as usize;
+                let tag = bytes
+                    .get(start..start + len)
+                    .ok_or_else(|| Error::Format("truncated gd3 tag".to_string()))?;
+                Some(tag.to_vec())
+            }
+            None => None,
+        };
+
+        let mut events = vec![];
+        let mut pointer = data_start;
+        loop {
+            if Some(pointer) == loop_start {
+                events.push(VgmEvent::LoopStart);
+            }
+
+            let command = *bytes
+                .get(pointer)
+                .ok_or_else(|| Error::Format("missing end of sound data".to_string()))?;
+            let operands = |count: usize| {
+                bytes.get(pointer + 1..pointer + 1 + count).ok_or_else(|| {
+                    Error::Format(format!(
+                        "truncated command {:#04x} at {:#x}",
+                        command, pointer
+                    ))
+                })
+            };
+
+            let (event, len) = match command {
+                0x50 => {
+                    let operands = operands(1)?;
+                    (VgmEvent::PsgWrite { value: operands[0] }, 2)
+                }
+                0x51..=0x5F | 0xA0..=0xBF => {
+                    let operands = operands(2)?;
+                    let event = VgmEvent::Write {
+                        command,
+                        register: operands[0],
+                        value: operands[1],
+                    };
+                    (event, 3)
+                }
+                0x61 => {
+                    let operands = operands(2)?;
+                    let samples = u16::from_le_bytes([operands[0], operands[1]]);
+                    (VgmEvent::Wait(samples as u32), 3)
+                }
+                0x62 => (VgmEvent::Wait(WAIT_NTSC), 1),
+                0x63 => (VgmEvent::Wait(WAIT_PAL), 1),
+                0x66 => (VgmEvent::End, 1),
+                0x67 => {
+                    let operands = operands(6)?;
+                    if operands[0] != 0x66 {
+                        return Err(Error::Format(format!(
+                            "invalid data block at {:#x}",
+                            pointer
+                        )));
+                    }
+                    let size = read_u32(operands, 2);
+                    let len = (size & !(1 << 31)) as usize;
+                    let data = bytes.get(pointer + 7..pointer + 7 + len).ok_or_else(|| {
+                        Error::Format(format!("truncated data block at {:#x}", pointer))
+                    })?;
+                    let event = VgmEvent::DataBlock {
+                        block_type: operands[1],
+                        second_chip: size & (1 << 31) != 0,
+                        data: data.to_vec(),
+                    };
+                    (event, 7 + len)
+                }
+                0x70..=0x7F => (VgmEvent::Wait((command & 0x0F) as u32 + 1), 1),
+                0x80..=0x8F => (VgmEvent::DacWriteWait(command & 0x0F), 1),
+                0xE0 => {
+                    let operands = operands(4)?;
+                    (VgmEvent::PcmSeek(read_u32(operands, 0)), 5)
+                }
+                _ => {
+                    let count = operand_count(command).ok_or_else(|| {
+                        Error::Format(format!(
+                            "unknown command {:#04x} at {:#x}",
+                            command, pointer
+                        ))
+                    })?;
+                    let event = VgmEvent::Other {
+                        command,
+                        operands: operands(count)?.to_vec(),
+                    };
+                    (event, 1 + count)
+                }
+            };
+
+            events.push(event);
+            if command == 0x66 {
+                break;
+            }
+            pointer += len;
+        }
+
+        if loop_start.is_some() && !events.contains(&VgmEvent::LoopStart) {
+            return Err(Error::Format(
+                "loop offset does not point to a command".to_string(),
+            ));
+        }
+
+        Ok(VgmFile {
+            header: bytes[..data_start].to_vec(),
+            events,
+            gd3,
+        })
+    }
+
+    /// Playable file with header offsets and sample counts matching the
+    /// events. An End is added if the events lack one. A short or empty
+    /// header is padded and gets the magic and DEFAULT_VERSION.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.header.clone();
+        out.resize(out.len().max(0x40), 0);
+        out[..4].copy_from_slice(MAGIC);
+        if read_u32(&out, VERSION) == 0 {
+            write_u32(&mut out, VERSION, DEFAULT_VERSION);
+        }
+        // commands follow the header
+        if read_u32(&out, VERSION) >= 0x150 {
+            let data_start = out.len();
+            write_offset(&mut out, DATA_OFFSET, Some(data_start));
+        }
+
+        let mut total_samples: u32 = 0;
+        let mut loop_position = None;
+        let mut loop_samples: u32 = 0;
+        for event in &self.events {
+            if *event == VgmEvent::End {
+                break;
+            }
+            if *event == VgmEvent::LoopStart {
+                loop_position = Some(out.len());
+                loop_samples = 0;
+            }
+            event.write_to(&mut out);
+            total_samples = total_samples.saturating_add(event.samples());
+            loop_samples = loop_samples.saturating_add(event.samples());
+        }
+        VgmEvent::End.write_to(&mut out);
+
+        let gd3_position = self.gd3.as_ref().map(|gd3| {
+            let position = out.len();
+            out.extend_from_slice(gd3);
+            position
+        });
+
+        let eof = out.len();
+        write_offset(&mut out, EOF_OFFSET, Some(eof));
+        write_offset(&mut out, GD3_OFFSET, gd3_position);
+        write_u32(&mut out, TOTAL_SAMPLES, total_samples);
+        write_offset(&mut out, LOOP_OFFSET, loop_position);
+        write_u32(
+            &mut out,
+            LOOP_SAMPLES,
+            loop_position.map_or(0, |_| loop_samples),
+        );
+        out
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        VgmFile::parse(&fs::read(path)?)
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn total_samples(&self) -> u64 {
+        self.events.iter().map(|event| event.samples() as u64).sum()
+    }
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[position],
+        bytes[position + 1],
+        bytes[position + 2],
+        bytes[position + 3],
+    ])
+}
+
+fn write_u32(bytes: &mut [u8], position: usize, value: u32) {
+    bytes[position..position + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Absolute position of a relative offset field, None when it is 0
+fn read_offset(bytes: &[u8], field: usize) -> Option<usize> {
+    match read_u32(bytes, field) {
+        0 => None,
+        offset => Some(field + offset as usize),
+    }
+}
+
+fn write_offset(bytes: &mut [u8], field: usize, position: Option<usize>) {
+    let offset = position.map_or(0, |position| (position - field) as u32);
+    write_u32(bytes, field, offset);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{VgmEvent, VgmFile};
+    use crate::generate;
+
+    /// Version 1.50 file with a data block, a loop and a GD3 tag
+    fn synthetic_vgm() -> Vec<u8> {
+        let mut bytes = vec![0; 0x40];
+        bytes[..4].copy_from_slice(b"Vgm ");
+        bytes[0x08..0x0C].copy_from_slice(&0x150u32.to_le_bytes());
+        bytes[0x34..0x38].copy_from_slice(&0x0Cu32.to_le_bytes());
+
+        bytes.extend_from_slice(&[0x67, 0x66, 0x00, 0x04, 0x00, 0x00, 0x00, 1, 2, 3, 4]);
+        bytes.extend_from_slice(&[0xE0, 0, 0, 0, 0]);
+        let loop_start = bytes.len();
+        for note in 0..4u8 {
+            bytes.extend_from_slice(&[0x50, 0x90 | note]);
+            bytes.extend_from_slice(&[0x52, 0x28, 0xF0]);
+            bytes.extend_from_slice(&[0x53, 0xA4, note]);
+            bytes.extend_from_slice(&[0x82, 0x62]);
+            bytes.extend_from_slice(&[0x61, 0xE8, 0x03]);
+            bytes.extend_from_slice(&[0x7F, 0x4F, 0x33]);
+        }
+        bytes.push(0x66);
+
+        let gd3_start = bytes.len();
+        bytes.extend_from_slice(b"Gd3 ");
+        bytes.extend_from_slice(&0x100u32.to_le_bytes());
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(&[b'a', 0, 0, 0]);
+
+        let eof = bytes.len();
+        bytes[0x04..0x08].copy_from_slice(&(eof as u32 - 0x04).to_le_bytes());
+        bytes[0x14..0x18].copy_from_slice(&(gd3_start as u32 - 0x14).to_le_bytes());
+        let samples = 4 * (2 + 735 + 1000 + 16);
+        bytes[0x18..0x1C].copy_from_slice(&(samples as u32).to_le_bytes());
+        bytes[0x1C..0x20].copy_from_slice(&(loop_start as u32 - 0x1C).to_le_bytes());
+        bytes[0x20..0x24].copy_from_slice(&(samples as u32).to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn parse_and_write_synthetic_file() {
+        let bytes = synthetic_vgm();
+        let vgm = VgmFile::parse(&bytes).unwrap();
+
+        assert_eq!(vgm.header.len(), 0x40);
+        assert_eq!(
+            vgm.events[..6],
+            [
+                VgmEvent::DataBlock {
+                    block_type: 0,
+                    second_chip: false,
+                    data: vec![1, 2, 3, 4],
+                },
+                VgmEvent::PcmSeek(0),
+                VgmEvent::LoopStart,
+                VgmEvent::PsgWrite { value: 0x90 },
+                VgmEvent::Write {
+                    command: 0x52,
+                    register: 0x28,
+                    value: 0xF0,
+                },
+                VgmEvent::Write {
+                    command: 0x53,
+                    register: 0xA4,
+                    value: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            vgm.events[6..9],
+            [
+                VgmEvent::DacWriteWait(2),
+                VgmEvent::Wait(735),
+                VgmEvent::Wait(1000),
+            ]
+        );
+        assert_eq!(
+            vgm.events[10],
+            VgmEvent::Other {
+                command: 0x4F,
+                operands: vec![0x33]
+            }
+        );
+        assert_eq!(vgm.events.last(), Some(&VgmEvent::End));
+        assert_eq!(vgm.total_samples(), 4 * (2 + 735 + 1000 + 16));
+
+        assert_eq!(vgm.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn tokenize_events() {
+        let vgm = VgmFile::parse(&synthetic_vgm()).unwrap();
+
+        let tokenizer = generate(&vgm.events, 24);
+        let mut token_buffer: Vec<usize> = vec![];
+        tokenizer.tokenize(&vgm.events, &mut token_buffer, &mut 0);
+        assert!(token_buffer.len() < vgm.events.len());
+
+        let mut events = vec![];
+        tokenizer.detokenize(&token_buffer, &mut events);
+        let decoded = VgmFile {
+            events,
+            ..vgm.clone()
+        };
+        assert_eq!(decoded, vgm);
+
+        // dropping the loop rewrites the header offsets
+        let mut events = vgm.events.clone();
+        events.retain(|event| *event != VgmEvent::LoopStart);
+        let bytes = VgmFile { events, ..vgm }.to_bytes();
+        assert_eq!(bytes[0x1C..0x24], [0; 8]);
+        assert!(!VgmFile::parse(&bytes)
+            .unwrap()
+            .events
+            .contains(&VgmEvent::LoopStart));
+    }
+
+    #[test]
+    fn write_without_header() {
+        let events = vec![
+            VgmEvent::PsgWrite { value: 0x90 },
+            VgmEvent::Wait(735),
+        ];
+        let vgm = VgmFile {
+            header: vec![],
+            events: events.clone(),
+            gd3: None,
+        };
+
+        let bytes = vgm.to_bytes();
+        assert_eq!(&bytes[..4], b"Vgm ");
+        let parsed = VgmFile::parse(&bytes).unwrap();
+        assert_eq!(parsed.events[..2], events);
+        assert_eq!(parsed.to_bytes(), bytes);
+    }
+}
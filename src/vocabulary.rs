@@ -0,0 +1,161 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, Merge, Tokenizer};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VocabularyEntry<T> {
+    pub token_value: usize,
+    pub elements: Vec<T>,
+}
+
+/// Serialized form of a tokenizer that only keeps the vocabulary ordered by
+/// token value, the merges, the special tokens and the training counts. The
+/// trie is rebuilt on load, so nothing is stored twice and the file can be
+/// edited by hand.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Vocabulary<T> {
+    pub tokens: Vec<VocabularyEntry<T>>,
+    #[serde(default)]
+    pub merges: Vec<Merge>,
+    #[serde(default)]
+    pub special_tokens: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub training_counts: BTreeMap<usize, usize>,
+}
+
+impl<T> Vocabulary<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    pub fn new(tokenizer: &Tokenizer<T>) -> Self {
+        let mut tokens: Vec<VocabularyEntry<T>> = tokenizer
+            .lookup
+            .iter()
+            .map(|(token_value, elements)| VocabularyEntry {
+                token_value: *token_value,
+                elements: elements.to_owned(),
+            })
+            .collect();
+        tokens.sort_unstable_by_key(|entry| entry.token_value);
+
+        Vocabulary {
+            tokens,
+            merges: tokenizer.merges(),
+            special_tokens: tokenizer
+                .special_tokens
+                .iter()
+                .map(|(name, token_value)| (name.to_owned(), *token_value))
+                .collect(),
+            training_counts: tokenizer
+                .training_counts
+                .iter()
+                .map(|(token_value, count)| (*token_value, *count))
+                .collect(),
+        }
+    }
+
+    /// Rebuild the tokenizer with Tokenizer::register, checking that token
+    /// values are unique, that merges agree with the vocabulary and that the
+    /// result passes Tokenizer::validate
+    pub fn to_tokenizer(&self) -> Result<Tokenizer<T>, Error> {
+        let tokenizer = self.to_tokenizer_unchecked()?;
+        tokenizer.validate().into_result()?;
+        Ok(tokenizer)
+    }
+
+    /// Same as to_tokenizer without Tokenizer::validate
+    pub fn to_tokenizer_unchecked(&self) -> Result<Tokenizer<T>, Error> {
+        let mut tokenizer = Tokenizer::default();
+        let mut seen: HashSet<usize> = HashSet::new();
+
+        for entry in &self.tokens {
+            if entry.elements.is_empty() {
+                return Err(Error::Format(format!("token {} is empty", entry.token_value)));
+            }
+            if !seen.insert(entry.token_value) {
+                return Err(Error::Format(format!(
+                    "token value {} is used twice",
+                    entry.token_value
+                )));
+            }
+            tokenizer.register(&entry.elements, entry.token_value);
+        }
+
+        for (name, token_value) in &self.special_tokens {
+            if !seen.insert(*token_value) {
+                return Err(Error::Format(format!(
+                    "special token {} reuses token value {}",
+                    name, token_value
+                )));
+            }
+            tokenizer.special_tokens.insert(name.to_owned(), *token_value);
+        }
+
+        tokenizer.check_merges(&self.merges)?;
+        tokenizer.training_counts = self
+            .training_counts
+            .iter()
+            .map(|(token_value, count)| (*token_value, *count))
+            .collect();
+
+        Ok(tokenizer)
+    }
+}
+
+impl<T> From<&Tokenizer<T>> for Vocabulary<T>
+where
+    T: Eq + Hash + Clone + Debug,
+{
+    fn from(tokenizer: &Tokenizer<T>) -> Self {
+        Vocabulary::new(tokenizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vocabulary;
+    use crate::{generate, test_data::RAW_TEXT};
+
+    #[test]
+    fn vocabulary_round_trip() {
+        let text_val: Vec<char> = RAW_TEXT.chars().take(2000).collect();
+        let mut tokenizer = generate(&text_val, 96);
+        tokenizer.add_special_token("<eos>");
+
+        let serialized = serde_json::to_string(&Vocabulary::from(&tokenizer)).unwrap();
+        let vocabulary: Vocabulary<char> = serde_json::from_str(&serialized).unwrap();
+        let rebuilt = vocabulary.to_tokenizer().unwrap();
+
+        assert_eq!(tokenizer.lookup, rebuilt.lookup);
+        assert_eq!(tokenizer.special_tokens, rebuilt.special_tokens);
+        assert_eq!(tokenizer.training_counts, rebuilt.training_counts);
+
+        let mut expected: Vec<usize> = vec![];
+        tokenizer.tokenize(&text_val, &mut expected, &mut 0);
+        let mut token_buffer: Vec<usize> = vec![];
+        rebuilt.tokenize(&text_val, &mut token_buffer, &mut 0);
+        assert_eq!(expected, token_buffer);
+
+        // an edited token no longer matches its merge
+        let mut edited = vocabulary.clone();
+        let merged = edited.merges[0].token_value;
+        let entry = edited
+            .tokens
+            .iter_mut()
+            .find(|entry| entry.token_value == merged)
+            .unwrap();
+        entry.elements.push('x');
+        assert!(edited.to_tokenizer().is_err());
+
+        // duplicated token value
+        let mut edited = vocabulary.clone();
+        edited.tokens.push(edited.tokens[0].clone());
+        assert!(edited.to_tokenizer().is_err());
+    }
+}
@@ -1,23 +1,60 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Debug,
-    hash::Hash, time::{Duration, Instant},
+    hash::Hash,
 };
 
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 use crate::Tokenizer;
 
+/// Base tokens get the values 0.. in the order they first appear in
+/// base_vocabulary
+pub fn parallel_generate_with_base_vocabulary<T>(
+    inputs: Vec<Vec<T>>,
+    base_vocabulary: Vec<T>,
+    target_vocabulary_size: usize,
+) -> Tokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug + Sync,
+{
+    parallel_generate_with_max_length(inputs, base_vocabulary, target_vocabulary_size, usize::MAX)
+}
 
+/// Same as parallel_generate_with_base_vocabulary, merges never produce tokens
+/// longer than max_token_length. Stops before target_vocabulary_size when no
+/// pair is left to merge
+pub fn parallel_generate_with_max_length<T>(
+    inputs: Vec<Vec<T>>,
+    base_vocabulary: Vec<T>,
+    target_vocabulary_size: usize,
+    max_token_length: usize,
+) -> Tokenizer<T>
+where
+    T: Eq + Hash + Clone + Debug + Sync,
+{
+    parallel_generate_with_key(
+        inputs,
+        base_vocabulary,
+        target_vocabulary_size,
+        max_token_length,
+        |_| (),
+    )
+}
 
-
-pub fn parallel_generate_with_base_vocabulary<T>(
+/// Same as parallel_generate_with_max_length, merges only join elements with
+/// the same key, like generate_with_key
+pub fn parallel_generate_with_key<T, K, F>(
     inputs: Vec<Vec<T>>,
     base_vocabulary: Vec<T>,
     target_vocabulary_size: usize,
+    max_token_length: usize,
+    key: F,
 ) -> Tokenizer<T>
 where
     T: Eq + Hash + Clone + Debug + Sync,
+    K: Eq,
+    F: Fn(&T) -> K + Sync,
 {
     let mut tokenizer = Tokenizer::default();
 
@@ -27,22 +64,42 @@ where
     // first pass
     let mut curr_token_value: usize = 0;
 
-    // perform dedup on base input
+    // occurrences of each base token, 0 for the ones missing from inputs
+    let base_counts: HashMap<&T, usize> = inputs
+        .par_iter()
+        .map(|curr_input| {
+            let mut counts: HashMap<&T, usize> = HashMap::new();
+            for elem in curr_input {
+                *counts.entry(elem).or_insert(0) += 1;
+            }
+            counts
+        })
+        .reduce(HashMap::new, |mut total, counts| {
+            for (elem, count) in counts {
+                *total.entry(elem).or_insert(0) += count;
+            }
+            total
+        });
+
+    // perform dedup on base input, keeping the given order so base token
+    // values are predictable
     {
         let mut token_set = HashSet::new();
         for elem in &base_vocabulary[..] {
-            token_set.insert(elem);
-        }
+            if !token_set.insert(elem) {
+                continue;
+            }
 
-        for elem in token_set {
             tokenizer.register(&[elem.to_owned()], curr_token_value);
+            tokenizer.training_counts.insert(
+                curr_token_value,
+                base_counts.get(elem).copied().unwrap_or(0),
+            );
             straight_lookup.insert(vec![elem.clone()], curr_token_value);
             curr_token_value += 1;
         }
     }
 
-    let now = Instant::now();
-
     // find data pairs
     while curr_token_value < target_vocabulary_size {
         let rslts: Vec<HashMap<&[T], usize>> = inputs
@@ -54,11 +111,13 @@ where
                 while pointer < curr_input.len() {
                     let curr_val_pointer = pointer;
 
-                    tokenizer.tokenize_item_no_write(curr_input, &mut pointer);
-                    //pointer += 1;
+                    tokenizer.find_longest(curr_input, &mut pointer);
 
-                    if pointer < curr_input.len() - 1 {
-                        //println!("pointer in larger: {}", pointer);
+                    if pointer + 1 < curr_input.len() {
+                        // the next token starts at a different key
+                        if key(&curr_input[pointer - 1]) != key(&curr_input[pointer]) {
+                            continue;
+                        }
                         pointer += 1;
                         pairs_count
                             .entry(&curr_input[curr_val_pointer..pointer])
@@ -76,42 +135,68 @@ where
             })
             .collect();
 
-        // consolidate all the pair counts 
+        // consolidate all the pair counts
         let mut consolidated_pair_counts: HashMap<&[T], usize> = HashMap::new();
         for pc in rslts {
             for (k, v) in pc.iter() {
                 consolidated_pair_counts
-                .entry(k)
-                .and_modify(|count| *count += v)
-                .or_insert(*v);
+                    .entry(k)
+                    .and_modify(|count| *count += v)
+                    .or_insert(*v);
             }
         }
 
         // find biggest that is not in tokenizer
-        let (max_key, _) = consolidated_pair_counts
+        let (max_key, max_count) = match consolidated_pair_counts
             .into_iter()
-            .filter(|(key, _)| straight_lookup.get(&key.to_owned().to_vec()).is_none())
+            .filter(|(key, _)| key.len() <= max_token_length && !straight_lookup.contains_key(*key))
             .max_by_key(|(_, pair_count)| *pair_count)
-            .unwrap();
+        {
+            Some(max) => max,
+            // nothing left to merge
+            None => break,
+        };
 
         // add biggest to tokenizer
         tokenizer.register(max_key, curr_token_value);
+        tokenizer
+            .training_counts
+            .insert(curr_token_value, max_count);
+        straight_lookup.insert(max_key.to_vec(), curr_token_value);
 
         // increment id tracker
         curr_token_value += 1;
     }
 
-    return tokenizer;
+    tokenizer
+}
+
+/// Byte level training for arbitrary binary inputs. All 256 bytes are in the
+/// base vocabulary with token value equal to the byte, so any file can be
+/// tokenized whatever the training inputs held
+pub fn parallel_generate_bytes(
+    inputs: Vec<Vec<u8>>,
+    target_vocabulary_size: usize,
+    max_token_length: usize,
+) -> Tokenizer<u8> {
+    parallel_generate_with_max_length(
+        inputs,
+        (0..=u8::MAX).collect(),
+        target_vocabulary_size,
+        max_token_length,
+    )
 }
 
 #[cfg(test)]
 mod tests_parallel {
     use crate::test_data::RAW_TEXT;
+    #[allow(unused_imports)]
     use std::{collections::HashSet, fs::File, io::Write};
 
     use super::parallel_generate_with_base_vocabulary;
 
     #[test]
+    #[allow(clippy::map_clone)]
     fn test_run_parallel() {
         //let text_val: Vec<char> = RAW_TEXT.chars().collect();
         let subsections: Vec<&str> = RAW_TEXT.split(".").collect();
@@ -166,4 +251,68 @@ mod tests_parallel {
         }
         */
     }
+
+    #[test]
+    fn base_values_follow_given_order() {
+        let inputs: Vec<Vec<char>> = vec!["abcabc".chars().collect()];
+
+        let tokenizer = parallel_generate_with_base_vocabulary(inputs, vec!['c', 'a', 'b', 'a'], 3);
+        assert_eq!(tokenizer.lookup[&0], vec!['c']);
+        assert_eq!(tokenizer.lookup[&1], vec!['a']);
+        assert_eq!(tokenizer.lookup[&2], vec!['b']);
+        assert_eq!(tokenizer.lookup.len(), 3);
+
+        // base tokens are counted like in the sequential trainer
+        assert_eq!(tokenizer.training_counts[&0], 2);
+        assert_eq!(tokenizer.training_counts.len(), 3);
+    }
+
+    #[test]
+    fn merge_within_key_only() {
+        let inputs: Vec<Vec<char>> = RAW_TEXT
+            .split('.')
+            .take(20)
+            .map(|sentence| sentence.chars().collect())
+            .collect();
+        let base_vocabulary: Vec<char> = inputs.iter().flatten().copied().collect();
+
+        let tokenizer =
+            super::parallel_generate_with_key(inputs, base_vocabulary, 120, usize::MAX, |elem| {
+                elem.is_whitespace()
+            });
+        assert!(tokenizer.lookup.values().any(|token| token.len() > 1));
+        for token in tokenizer.lookup.values() {
+            assert!(token
+                .iter()
+                .all(|elem| elem.is_whitespace() == token[0].is_whitespace()));
+        }
+    }
+
+    #[test]
+    fn bytes_cover_every_value() {
+        let inputs: Vec<Vec<u8>> = RAW_TEXT
+            .as_bytes()
+            .chunks(400)
+            .take(5)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+
+        let tokenizer = super::parallel_generate_bytes(inputs, 320, 8);
+        for byte in 0..=u8::MAX {
+            assert_eq!(tokenizer.lookup[&(byte as usize)], vec![byte]);
+        }
+        assert!(tokenizer.lookup.values().all(|token| token.len() <= 8));
+
+        // bytes never seen in training still encode
+        let unseen: Vec<u8> = (0..=u8::MAX)
+            .rev()
+            .chain(RAW_TEXT.bytes().take(300))
+            .collect();
+        let mut token_buffer: Vec<usize> = vec![];
+        tokenizer.tokenize(&unseen, &mut token_buffer, &mut 0);
+
+        let mut detokenized = vec![];
+        tokenizer.detokenize(&token_buffer, &mut detokenized);
+        assert_eq!(detokenized, unseen);
+    }
 }
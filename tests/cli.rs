@@ -0,0 +1,172 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const CORPUS: &str = "the quick brown fox jumps over the lazy dog
+the lazy dog sleeps while the quick fox runs
+a fox and a dog are the best of friends
+";
+
+/// Directory removed on drop so failing tests do not leave it behind
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("bpe_cli_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn join(&self, name: &str) -> String {
+        self.0.join(name).to_str().unwrap().to_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn bpe(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bpe"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn bpe_ok(args: &[&str]) -> String {
+    let output = bpe(args);
+    assert!(
+        output.status.success(),
+        "bpe {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn encode_decode(directory: &TempDir, tokenizer: &str, binary: &[&str]) {
+    let corpus = directory.join("corpus.txt");
+    let ids = directory.join("ids");
+    let decoded = directory.join("decoded.txt");
+
+    bpe_ok(
+        &[
+            &["encode", "--tokenizer", tokenizer, "--output", &ids],
+            binary,
+            &[&corpus],
+        ]
+        .concat(),
+    );
+    bpe_ok(
+        &[
+            &["decode", "--tokenizer", tokenizer, "--output", &decoded],
+            binary,
+            &[&ids],
+        ]
+        .concat(),
+    );
+    assert_eq!(fs::read_to_string(&decoded).unwrap(), CORPUS);
+}
+
+#[test]
+fn train_encode_decode_inspect() {
+    let directory = TempDir::new("text");
+    let corpus = directory.join("corpus.txt");
+    fs::write(&corpus, CORPUS).unwrap();
+
+    for name in ["tokenizer.json", "tokenizer.vocab.json", "tokenizer.bin"] {
+        let tokenizer = directory.join(name);
+        bpe_ok(&[
+            "train",
+            "--vocab-size",
+            "48",
+            "--special",
+            "<eos>",
+            "--output",
+            &tokenizer,
+            &corpus,
+        ]);
+        assert!(Path::new(&tokenizer).exists());
+
+        encode_decode(&directory, &tokenizer, &[]);
+        encode_decode(&directory, &tokenizer, &["--binary"]);
+
+        let stats = bpe_ok(&["inspect", "--tokenizer", &tokenizer]);
+        assert!(stats.contains("vocabulary size: 49"), "{}", stats);
+        assert!(stats.contains("  48 <eos>"), "{}", stats);
+        assert!(stats.contains("validation: ok"), "{}", stats);
+
+        let tsv = bpe_ok(&["inspect", "--tokenizer", &tokenizer, "--dump", "tsv"]);
+        assert_eq!(tsv.lines().count(), 50);
+    }
+
+    // tokens shorter than the text, ids as text are one line
+    let ids = bpe_ok(&[
+        "encode",
+        "--tokenizer",
+        &directory.join("tokenizer.json"),
+        &corpus,
+    ]);
+    assert_eq!(ids.lines().count(), 1);
+    assert!(ids.split_whitespace().count() < CORPUS.len());
+}
+
+#[test]
+fn train_on_bytes() {
+    let directory = TempDir::new("bytes");
+    let corpus = directory.join("corpus.txt");
+    fs::write(&corpus, CORPUS).unwrap();
+
+    for name in ["tokenizer.bin", "tokenizer.json", "tokenizer.vocab.json"] {
+        let tokenizer = directory.join(name);
+        bpe_ok(&[
+            "train",
+            "--bytes",
+            "--vocab-size",
+            "300",
+            "--output",
+            &tokenizer,
+            &corpus,
+        ]);
+        // byte tokenizers are detected from the file
+        encode_decode(&directory, &tokenizer, &[]);
+
+        let stats = bpe_ok(&["inspect", "--tokenizer", &tokenizer]);
+        assert!(stats.contains("base tokens: 256"), "{}", stats);
+    }
+}
+
+#[test]
+fn report_errors() {
+    let directory = TempDir::new("errors");
+    let corpus = directory.join("corpus.txt");
+    fs::write(&corpus, CORPUS).unwrap();
+    let tokenizer = directory.join("tokenizer.json");
+    bpe_ok(&[
+        "train",
+        "--vocab-size",
+        "40",
+        "--output",
+        &tokenizer,
+        &corpus,
+    ]);
+
+    let unknown = directory.join("unknown.txt");
+    fs::write(&unknown, "ZEBRA").unwrap();
+    let output = bpe(&["encode", "--tokenizer", &tokenizer, &unknown]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not in the vocabulary"));
+
+    let ids = directory.join("ids.txt");
+    fs::write(&ids, "1 2 100000").unwrap();
+    let output = bpe(&["decode", "--tokenizer", &tokenizer, &ids]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown token value 100000"));
+
+    assert!(!bpe(&["frobnicate"]).status.success());
+    assert!(bpe_ok(&["--help"]).starts_with("usage: bpe"));
+}
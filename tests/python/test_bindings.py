@@ -0,0 +1,93 @@
+"""Smoke test of the bpe_tokenizer module.
+
+Build it with `maturin develop --release`, then run
+`python -m unittest discover tests/python`.
+"""
+
+import os
+import tempfile
+import unittest
+
+import bpe_tokenizer
+
+TEXTS = [
+    "the quick brown fox jumps over the lazy dog",
+    "the lazy dog sleeps while the quick fox runs",
+    "a fox and a dog are the best of friends",
+]
+
+
+class CharTokenizerTest(unittest.TestCase):
+    def setUp(self):
+        self.tokenizer = bpe_tokenizer.CharTokenizer.train(TEXTS, 48)
+
+    def test_encode_decode(self):
+        ids = self.tokenizer.encode(TEXTS[0])
+        self.assertLess(len(ids), len(TEXTS[0]))
+        self.assertEqual(self.tokenizer.decode(ids), TEXTS[0])
+        self.assertEqual(len(self.tokenizer), 48)
+
+        self.assertEqual(
+            self.tokenizer.encode_batch(TEXTS),
+            [self.tokenizer.encode(text) for text in TEXTS],
+        )
+        with self.assertRaises(ValueError):
+            self.tokenizer.encode("unknown characters: é")
+
+    def test_special_tokens(self):
+        eos = self.tokenizer.add_special_token("<eos>")
+        self.assertEqual(self.tokenizer.special_token("<eos>"), eos)
+        self.assertEqual(self.tokenizer.special_tokens, {"<eos>": eos})
+        self.assertIsNone(self.tokenizer.special_token("<pad>"))
+
+        # special tokens are skipped on decode
+        ids = self.tokenizer.encode(TEXTS[1]) + [eos]
+        self.assertEqual(self.tokenizer.decode(ids), TEXTS[1])
+
+    def test_save_load(self):
+        self.tokenizer.add_special_token("<eos>")
+        with tempfile.TemporaryDirectory() as directory:
+            for name in ["tokenizer.json", "tokenizer.vocab.json", "tokenizer.bin"]:
+                path = os.path.join(directory, name)
+                self.tokenizer.save(path)
+                loaded = bpe_tokenizer.CharTokenizer.load(path)
+
+                self.assertEqual(loaded.vocabulary(), self.tokenizer.vocabulary())
+                self.assertEqual(loaded.special_tokens, self.tokenizer.special_tokens)
+                for text in TEXTS:
+                    self.assertEqual(loaded.encode(text), self.tokenizer.encode(text))
+
+
+class ByteTokenizerTest(unittest.TestCase):
+    def setUp(self):
+        self.data = [text.encode() for text in TEXTS]
+        self.tokenizer = bpe_tokenizer.ByteTokenizer.train(self.data, 300)
+
+    def test_encode_decode(self):
+        ids = self.tokenizer.encode(self.data[0])
+        self.assertLess(len(ids), len(self.data[0]))
+        self.assertEqual(self.tokenizer.decode(ids), self.data[0])
+
+        # every byte is in the vocabulary
+        self.assertEqual(self.tokenizer.decode(self.tokenizer.encode(b"\xff\x00")), b"\xff\x00")
+        self.assertEqual(
+            self.tokenizer.encode_batch(self.data),
+            [self.tokenizer.encode(data) for data in self.data],
+        )
+        with self.assertRaises(ValueError):
+            self.tokenizer.decode([100000])
+
+    def test_save_load(self):
+        eos = self.tokenizer.add_special_token("<eos>")
+        with tempfile.TemporaryDirectory() as directory:
+            path = os.path.join(directory, "tokenizer.bin")
+            self.tokenizer.save(path)
+            loaded = bpe_tokenizer.ByteTokenizer.load(path)
+
+            self.assertEqual(loaded.special_token("<eos>"), eos)
+            self.assertEqual(loaded.vocabulary(), self.tokenizer.vocabulary())
+            self.assertEqual(loaded.encode(self.data[2]), self.tokenizer.encode(self.data[2]))
+
+
+if __name__ == "__main__":
+    unittest.main()